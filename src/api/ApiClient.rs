@@ -1,7 +1,7 @@
 use std::borrow::Borrow;
 use std::collections::HashMap;
-use reqwest::{Client, Response};
-use anyhow::Result;
+use reqwest::{Client, RequestBuilder, Response};
+use anyhow::{anyhow, Result};
 use serde_json::Value;
 use std::sync::atomic::AtomicBool;
 use serde::de::DeserializeOwned;
@@ -9,6 +9,7 @@ use tokio::sync::Mutex;
 
 pub struct ApiClient {
     pub(crate) apikey: String,
+    pub(crate) access_token: Option<String>,
     DEBUG_MODE: Mutex<AtomicBool>,
 }
 
@@ -16,10 +17,20 @@ impl ApiClient {
     pub fn new(apikey: &str) -> Self {
         Self {
             apikey: String::from(apikey),
+            access_token: None,
             DEBUG_MODE: Mutex::new(AtomicBool::new(false)),
         }
     }
 
+    /// Attach an OAuth access token to every request made by this client
+    ///
+    /// Required for any endpoint that acts on behalf of a user, such as
+    /// transferring or equipping items.
+    pub fn with_access_token(mut self, token: &str) -> Self {
+        self.access_token = Some(String::from(token));
+        self
+    }
+
     /// Enables Debug Mode
     ///
     /// Prints all requests and their responses as they come through
@@ -40,6 +51,7 @@ impl ApiClient {
     pub async fn clone(&self) -> Self {
         Self {
             apikey: self.apikey.clone(),
+            access_token: self.access_token.clone(),
             DEBUG_MODE: Mutex::new(AtomicBool::new(self.is_debug_enabled().await)),
         }
     }
@@ -50,8 +62,7 @@ impl ApiClient {
 
     pub async fn get_params(&self, url: String, map: HashMap<&str, &str>) -> Result<String> {
         let client = reqwest::Client::new();
-        let resp = client
-            .get(url.clone())
+        let resp = self.authorize(client.get(url.clone()))
             .header("X-API-KEY", self.apikey.as_str())
             .query(&map)
             .send()
@@ -68,6 +79,25 @@ impl ApiClient {
         Ok(text)
     }
 
+    /// Download a file as bytes, such as the zipped manifest database
+    pub async fn get_bytes(&self, url: String) -> Result<Vec<u8>> {
+        let client = Client::new();
+        let resp = self.authorize(client.get(url.clone()))
+            .header("X-API-KEY", self.apikey.as_str())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let bytes = resp.bytes().await?;
+
+        if self.is_debug_enabled().await {
+            println!("GET {}", url);
+            println!("{} bytes", bytes.len());
+        }
+
+        Ok(bytes.to_vec())
+    }
+
     pub async fn get_parse<T: DeserializeOwned>(&self, url: String, dewrap: bool) -> Result<T> {
         if dewrap {
             let val = serde_json::from_str::<Value>(self.get(url.clone()).await?.as_str())?;
@@ -100,8 +130,7 @@ impl ApiClient {
 
     pub async fn post_params(&self, url: String, body: String, map: HashMap<&str, &str>) -> Result<String> {
         let client = Client::new();
-        let resp = client
-            .post(url.clone())
+        let resp = self.authorize(client.post(url.clone()))
             .body(body.clone())
             .header("X-API-KEY", self.apikey.as_str())
             .query(&map)
@@ -144,4 +173,34 @@ impl ApiClient {
 
         Ok(serde_json::from_str::<T>(text.as_str())?)
     }
+
+    /// Post to an action endpoint and return the "Response" field
+    ///
+    /// Unlike post_parse(), this fails if Bungie returns anything other than
+    /// a successful ErrorCode, since actions often respond with a null body
+    /// when they are rejected.
+    pub async fn post_action(&self, url: String, body: String) -> Result<Value> {
+        let val = serde_json::from_str::<Value>(self.post(url, body).await?.as_str())?;
+
+        ApiClient::check_error_code(&val)?;
+
+        Ok(val["Response"].clone())
+    }
+
+    /// Fail if the response does not have an ErrorCode of 1 (Success)
+    pub fn check_error_code(val: &Value) -> Result<()> {
+        if val["ErrorCode"].as_i64() != Some(1) {
+            return Err(anyhow!("{} - {}", val["ErrorStatus"].as_str().unwrap_or("Unknown"), val["Message"].as_str().unwrap_or("No message was provided")));
+        }
+
+        Ok(())
+    }
+
+    fn authorize(&self, builder: RequestBuilder) -> RequestBuilder {
+        if let Some(token) = &self.access_token {
+            return builder.bearer_auth(token);
+        }
+
+        builder
+    }
 }
@@ -44,4 +44,4 @@ impl ApiInterface {
 
 // Other
 pub const URL_BASE: &str = "https://www.bungie.net/Platform";
-
+pub const BUNGIE_BASE: &str = "https://www.bungie.net";
@@ -146,7 +146,7 @@ pub mod macros {
 
     #[derive(Deserialize, Serialize, Clone)]
     pub struct Basic {
-        pub value: f32,
+        pub value: f64,
         pub displayValue: String,
     }
 
@@ -164,7 +164,7 @@ pub mod macros {
     /// to get the value that it stores.
     ///
     /// Very useful within the API because there are a lot of enum types that contain values
-    /// such as ActivityIdentifier, ActivityMode and DestinyPlatform.
+    /// such as ActivityMode, DestinyPlatform and ManifestEntityType.
     #[macro_export]
     macro_rules! enumize {
         ($name: ident, $y: ty => {
@@ -223,8 +223,8 @@ pub mod macros {
             B, "B type".to_string()
         });
 
-        for t in StringEnum::get_all() {
-            println!("{}", t.get());
+        for e in StringEnum::get_all() {
+            println!("{}", e.get());
         }
     }
 }
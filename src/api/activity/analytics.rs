@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use crate::api::activity::activity::{ActivityDetails, ActivityHistoryResponse, ActivityMode, Entry, PGCR};
+use crate::api::activity::resolver::ActivityResolver;
+#[cfg(test)]
+use crate::api::activity::activity::{test_entry, test_pgcr};
+use crate::enumize;
+
+// A clear with fewer players than the activity is made for, by the number of players who completed it
+//
+// Compared to the fireteam size, so a trio is a lowman in a raid but a full dungeon fireteam
+enumize!(Lowman, i32 => {
+    Solo, 1,
+    Duo, 2,
+    Trio, 3
+});
+
+/// What a single raid or dungeon PGCR says about the run
+#[derive(Clone)]
+pub struct ClearAnalysis {
+    pub instanceId: i64,
+    pub period: NaiveDateTime,
+    pub activityHash: i64,
+    /// The raid or dungeon regardless of difficulty, see ActivityResolver
+    pub canonicalHash: i64,
+    pub name: String,
+    /// Whether anyone was there at the end
+    pub completed: bool,
+    /// Completed and started from the beginning rather than from a checkpoint
+    pub fullClear: bool,
+    /// A full clear in which nobody who completed it died
+    pub flawless: bool,
+    pub lowman: Option<Lowman>,
+    pub durationSeconds: f64,
+    /// The membership ids of the players who completed it
+    pub completers: Vec<String>,
+}
+
+impl ClearAnalysis {
+    /// Analyze a PGCR, which doesn't have to be of a raid or dungeon
+    ///
+    /// A run counts as a full clear if Bungie says it was started from the beginning,
+    /// or if it started at the first phase for PGCRs from before that was recorded.
+    pub fn new(pgcr: &PGCR, resolver: &ActivityResolver) -> Result<Self> {
+        let details = &pgcr.activityDetails;
+        let resolved = resolver.resolve_details(details);
+        let (canonical_hash, name) = match resolved {
+            Some(activity) => (activity.canonicalHash, activity.canonicalName.clone()),
+            None => (details.referenceId, String::new()),
+        };
+        let fireteam_size = resolved.and_then(|a| a.maxParty).map(|m| m as usize).unwrap_or_else(|| get_fireteam_size(details));
+
+        let mut completers = vec![];
+
+        for entry in pgcr.entries.iter().filter(|e| ClearAnalysis::is_completer(e)) {
+            if !completers.contains(&entry.player.destinyUserInfo.id) {
+                completers.push(entry.player.destinyUserInfo.id.clone());
+            }
+        }
+
+        // A player who swapped characters has an entry for each, and dying on either counts
+        let deaths = pgcr.entries.iter()
+            .filter(|e| completers.contains(&e.player.destinyUserInfo.id))
+            .map(|e| e.values.get_deaths())
+            .sum::<f64>();
+
+        let completed = !completers.is_empty();
+        let full_clear = completed && (pgcr.activityWasStartedFromBeginning || pgcr.startingPhaseIndex == 0);
+
+        Ok(Self {
+            instanceId: details.instanceId.parse::<i64>()?,
+            period: pgcr.period,
+            activityHash: details.referenceId,
+            canonicalHash: canonical_hash,
+            name,
+            completed,
+            fullClear: full_clear,
+            flawless: full_clear && deaths == 0.0,
+            lowman: if completed && completers.len() < fireteam_size { Lowman::from(completers.len() as i32) } else { None },
+            durationSeconds: pgcr.entries.iter().map(|e| e.values.get_activity_duration_seconds()).fold(0.0, f64::max),
+            completers,
+        })
+    }
+
+    /// Whether a player was there when the activity was completed, rather than leaving or failing
+    pub fn is_completer(entry: &Entry) -> bool {
+        entry.values.is_completed() && entry.values.get_completion_reason() == 0.0
+    }
+}
+
+/// Whether an activity is a raid or a dungeon
+pub fn is_raid_or_dungeon(details: &ActivityDetails) -> bool {
+    let modes = [ActivityMode::Raid.get(), ActivityMode::Dungeon.get()];
+
+    modes.contains(&(details.mode as i16)) || details.modes.iter().any(|m| modes.contains(m))
+}
+
+/// The fireteam size of a raid or dungeon when the definition doesn't say, 3 for dungeons and 6 for raids
+pub fn get_fireteam_size(details: &ActivityDetails) -> usize {
+    let dungeon = ActivityMode::Dungeon.get();
+
+    if details.mode as i16 == dungeon || details.modes.contains(&dungeon) { 3 } else { 6 }
+}
+
+/// Clears of a raid or dungeon, across every difficulty
+#[derive(Clone)]
+pub struct RaidSummary {
+    pub canonicalHash: i64,
+    pub name: String,
+    /// Completed runs among the PGCRs that were added
+    pub clears: usize,
+    pub fullClears: usize,
+    pub flawless: usize,
+    /// The fastest full clear
+    pub fastest: Option<ClearAnalysis>,
+    pub lowmans: Vec<ClearAnalysis>,
+    pub players: HashMap<String, PlayerClears>,
+}
+
+impl RaidSummary {
+    /// Lowman clears of a size, e.g. every trio
+    pub fn get_lowmans(&self, lowman: Lowman) -> Vec<&ClearAnalysis> {
+        self.lowmans.iter().filter(|c| c.lowman == Some(lowman)).collect()
+    }
+
+    /// Players sorted by their number of clears, most first
+    pub fn get_leaderboard(&self) -> Vec<&PlayerClears> {
+        let mut players = self.players.values().collect::<Vec<&PlayerClears>>();
+        players.sort_by(|a, b| b.clears.cmp(&a.clears).then(a.membershipId.cmp(&b.membershipId)));
+
+        players
+    }
+}
+
+/// How many times a player has completed a raid or dungeon
+///
+/// Clears found only in activity history count towards clears, but not towards
+/// full clears, flawless or lowman, since only a PGCR shows those.
+#[derive(Clone, Default)]
+pub struct PlayerClears {
+    pub membershipId: String,
+    pub clears: usize,
+    pub fullClears: usize,
+    pub flawless: usize,
+    pub lowmans: usize,
+    pub fastestSeconds: Option<f64>,
+}
+
+/// Raid and dungeon clears grouped by canonical activity, built from PGCRs and activity history
+///
+/// The same run can be added through several players' histories and its PGCR without
+/// being counted more than once.
+pub struct RaidAnalytics<'a> {
+    resolver: &'a ActivityResolver,
+    raids: HashMap<i64, RaidSummary>,
+    instances: HashSet<i64>,
+    /// Which player clears have been counted, and whether that was from a PGCR
+    counted: HashMap<(String, i64), bool>,
+}
+
+impl<'a> RaidAnalytics<'a> {
+    pub fn new(resolver: &'a ActivityResolver) -> Self {
+        Self {
+            resolver,
+            raids: HashMap::new(),
+            instances: HashSet::new(),
+            counted: HashMap::new(),
+        }
+    }
+
+    /// Add a PGCR, returns its analysis if it was a raid or dungeon that hadn't been added yet
+    pub fn add_pgcr(&mut self, pgcr: &PGCR) -> Result<Option<ClearAnalysis>> {
+        if !is_raid_or_dungeon(&pgcr.activityDetails) {
+            return Ok(None);
+        }
+
+        let clear = ClearAnalysis::new(pgcr, self.resolver)?;
+
+        if !self.instances.insert(clear.instanceId) || !clear.completed {
+            return Ok(None);
+        }
+
+        let raid = RaidAnalytics::get_summary(&mut self.raids, clear.canonicalHash, &clear.name);
+
+        raid.clears += 1;
+        raid.fullClears += clear.fullClear as usize;
+        raid.flawless += clear.flawless as usize;
+
+        if clear.fullClear && raid.fastest.as_ref().map(|f| clear.durationSeconds < f.durationSeconds).unwrap_or(true) {
+            raid.fastest = Some(clear.clone());
+        }
+
+        if clear.lowman.is_some() {
+            raid.lowmans.push(clear.clone());
+        }
+
+        for membership_id in &clear.completers {
+            let player = raid.players.entry(membership_id.clone()).or_insert_with(|| PlayerClears {
+                membershipId: membership_id.clone(),
+                ..Default::default()
+            });
+
+            // Already counted from activity history, but only now known to be a full clear
+            if self.counted.insert((membership_id.clone(), clear.instanceId), true).is_none() {
+                player.clears += 1;
+            }
+
+            player.fullClears += clear.fullClear as usize;
+            player.flawless += clear.flawless as usize;
+            player.lowmans += clear.lowman.is_some() as usize;
+
+            if clear.fullClear && player.fastestSeconds.map(|f| clear.durationSeconds < f).unwrap_or(true) {
+                player.fastestSeconds = Some(clear.durationSeconds);
+            }
+        }
+
+        Ok(Some(clear))
+    }
+
+    /// Count a clear from a player's activity history, returns whether it was counted
+    pub fn add_history(&mut self, membership_id: &str, activity: &ActivityHistoryResponse) -> Result<bool> {
+        let details = &activity.activityDetails;
+
+        if !is_raid_or_dungeon(details) || !activity.values.is_completed() || activity.values.get_completion_reason() != 0.0 {
+            return Ok(false);
+        }
+
+        let instance_id = details.instanceId.parse::<i64>()?;
+
+        if self.counted.contains_key(&(membership_id.to_string(), instance_id)) {
+            return Ok(false);
+        }
+
+        let (canonical_hash, name) = match self.resolver.resolve_details(details) {
+            Some(resolved) => (resolved.canonicalHash, resolved.canonicalName.clone()),
+            None => (details.referenceId, String::new()),
+        };
+
+        let raid = RaidAnalytics::get_summary(&mut self.raids, canonical_hash, &name);
+        let player = raid.players.entry(membership_id.to_string()).or_insert_with(|| PlayerClears {
+            membershipId: membership_id.to_string(),
+            ..Default::default()
+        });
+
+        player.clears += 1;
+        self.counted.insert((membership_id.to_string(), instance_id), false);
+
+        Ok(true)
+    }
+
+    /// Every raid and dungeon with at least one clear, sorted by name
+    pub fn get_raids(&self) -> Vec<&RaidSummary> {
+        let mut raids = self.raids.values().collect::<Vec<&RaidSummary>>();
+        raids.sort_by(|a, b| a.name.cmp(&b.name).then(a.canonicalHash.cmp(&b.canonicalHash)));
+
+        raids
+    }
+
+    /// Get a raid by the hash of any of its variants
+    pub fn get_raid(&self, hash: i64) -> Option<&RaidSummary> {
+        let canonical = self.resolver.resolve(hash).map(|a| a.canonicalHash).unwrap_or(hash);
+
+        self.raids.get(&canonical)
+    }
+
+    /// A player's clears of every raid and dungeon
+    pub fn get_player(&self, membership_id: &str) -> Vec<(&RaidSummary, &PlayerClears)> {
+        self.get_raids().into_iter()
+            .filter_map(|raid| raid.players.get(membership_id).map(|player| (raid, player)))
+            .collect()
+    }
+
+    fn get_summary<'b>(raids: &'b mut HashMap<i64, RaidSummary>, canonical_hash: i64, name: &str) -> &'b mut RaidSummary {
+        raids.entry(canonical_hash).or_insert_with(|| RaidSummary {
+            canonicalHash: canonical_hash,
+            name: name.to_string(),
+            clears: 0,
+            fullClears: 0,
+            flawless: 0,
+            fastest: None,
+            lowmans: vec![],
+            players: HashMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_raid(instance_id: i64, hash: i64, mode: ActivityMode, fresh: bool, duration: f64, players: &[(&str, f64, f64)]) -> PGCR {
+    let entries = players.iter().map(|(id, completed, deaths)| test_entry(id, id, 1, *completed, 0.0, *deaths, duration)).collect();
+
+    PGCR::new(test_pgcr(instance_id, hash, mode, "2022-01-01T20:00:00Z", fresh, entries)).unwrap()
+}
+
+#[test]
+fn raid_analytics_clears() {
+    let definitions = serde_json::from_str::<Vec<crate::api::manifest::definitions::DestinyActivityDefinition>>(r#"[
+        {"hash": 3881495763, "displayProperties": {"name": "Vault of Glass"}, "activityTypeHash": 2043403989, "directActivityModeType": 4},
+        {"hash": 1681562271, "displayProperties": {"name": "Vault of Glass: Master"}, "activityTypeHash": 2043403989, "directActivityModeType": 4, "matchmaking": {"maxParty": 6}},
+        {"hash": 2582501063, "displayProperties": {"name": "Pit of Heresy"}, "activityTypeHash": 608898761, "directActivityModeType": 82}
+    ]"#).unwrap();
+
+    let resolver = ActivityResolver::from_definitions(definitions, HashMap::new(), HashMap::new(), HashMap::new());
+    let mut analytics = RaidAnalytics::new(&resolver);
+
+    // A flawless trio on Master, where the fourth player left before the end
+    let trio = analytics.add_pgcr(&test_raid(1, 1681562271, ActivityMode::Raid, true, 1800.0, &[("a", 1.0, 0.0), ("b", 1.0, 0.0), ("c", 1.0, 0.0), ("d", 0.0, 5.0)])).unwrap().unwrap();
+    assert!(trio.fullClear && trio.flawless);
+    assert!(trio.lowman == Some(Lowman::Trio));
+    assert_eq!(3881495763, trio.canonicalHash);
+
+    // A faster checkpoint clear doesn't count as the fastest clear
+    let checkpoint = analytics.add_pgcr(&test_raid(2, 3881495763, ActivityMode::Raid, false, 600.0, &[("a", 1.0, 1.0), ("e", 1.0, 0.0), ("f", 1.0, 0.0), ("g", 1.0, 0.0), ("h", 1.0, 0.0), ("i", 1.0, 0.0)])).unwrap().unwrap();
+    assert!(!checkpoint.fullClear && !checkpoint.flawless && checkpoint.lowman.is_none());
+
+    // Adding a PGCR again changes nothing
+    assert!(analytics.add_pgcr(&test_raid(2, 3881495763, ActivityMode::Raid, false, 600.0, &[("a", 1.0, 1.0)])).unwrap().is_none());
+
+    let raid = analytics.get_raid(1681562271).unwrap();
+    assert_eq!("Vault of Glass", raid.name);
+    assert_eq!((2, 1, 1), (raid.clears, raid.fullClears, raid.flawless));
+    assert_eq!(1, raid.fastest.as_ref().unwrap().instanceId);
+    assert_eq!(1, raid.get_lowmans(Lowman::Trio).len());
+    assert_eq!("a", raid.get_leaderboard()[0].membershipId);
+    assert!(!raid.players.contains_key("d"));
+
+    let player = &analytics.get_player("a")[0].1;
+    assert_eq!((2, 1, 1, 1), (player.clears, player.fullClears, player.flawless, player.lowmans));
+    assert_eq!(Some(1800.0), player.fastestSeconds);
+
+    // Three players is a full dungeon fireteam, so only fewer is a lowman
+    let dungeon = |instance_id: i64, players: &[(&str, f64, f64)]| test_raid(instance_id, 2582501063, ActivityMode::Dungeon, true, 1200.0, players);
+
+    let full = analytics.add_pgcr(&dungeon(3, &[("a", 1.0, 0.0), ("b", 1.0, 0.0), ("c", 1.0, 0.0)])).unwrap().unwrap();
+    assert!(full.lowman.is_none());
+
+    let duo = analytics.add_pgcr(&dungeon(4, &[("a", 1.0, 0.0), ("b", 1.0, 0.0)])).unwrap().unwrap();
+    assert!(duo.lowman == Some(Lowman::Duo));
+    assert_eq!(1, analytics.get_raid(2582501063).unwrap().lowmans.len());
+}
@@ -0,0 +1,368 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, Semaphore};
+use crate::api::ApiClient::ApiClient;
+use crate::api::activity::activity::{PGCR, PgcrScraper};
+
+/// Where crawled PGCRs go, such as a database or an archive
+///
+/// PGCRs arrive in the order they finish downloading, not in id order. After a crash
+/// the crawl resumes from the last checkpoint, so a few PGCRs may be given to the
+/// sink twice and it should tolerate that, e.g. by upserting.
+#[allow(async_fn_in_trait)]
+pub trait PgcrSink {
+    /// Store a PGCR, the raw JSON is what Bungie returned and may contain fields PGCR doesn't model
+    async fn accept(&mut self, pgcr: &PGCR, raw: &Value) -> Result<()>;
+
+    /// Make everything accepted so far durable, called before each checkpoint is written
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Collect PGCRs in memory
+impl PgcrSink for Vec<PGCR> {
+    async fn accept(&mut self, pgcr: &PGCR, _raw: &Value) -> Result<()> {
+        self.push(pgcr.clone());
+        Ok(())
+    }
+}
+
+/// Downloads PGCRs in bulk, several at a time
+///
+/// Activities that don't exist are skipped, and requests that keep failing are
+/// recorded in the report instead of stopping the crawl. With a checkpoint file,
+/// progress is saved as the crawl goes so it can pick up where it left off.
+pub struct PgcrCrawler {
+    scraper: Arc<PgcrScraper>,
+    concurrency: usize,
+    retries: u32,
+    retry_delay: Duration,
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: u64,
+}
+
+/// The progress of a crawl, saved to the checkpoint file
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct CrawlReport {
+    /// Identifies the crawl, so a checkpoint isn't resumed by a different crawl
+    pub key: String,
+    /// Everything before this position in the range or list has been handled
+    pub position: u64,
+    /// Positions after position that have been handled as well, so they aren't fetched again on resume
+    #[serde(default)]
+    pub completed: Vec<u64>,
+    pub total: u64,
+    pub fetched: u64,
+    pub missing: u64,
+    /// Ids that still failed after every retry, or couldn't be parsed
+    pub failed: Vec<i64>,
+}
+
+impl CrawlReport {
+    pub fn is_complete(&self) -> bool {
+        self.position >= self.total
+    }
+}
+
+/// What is being crawled, without materializing a range of ids
+enum CrawlTarget {
+    Range(i64, i64),
+    Ids(Vec<i64>),
+}
+
+impl CrawlTarget {
+    fn len(&self) -> u64 {
+        match self {
+            CrawlTarget::Range(start, end) => (end - start).max(0) as u64,
+            CrawlTarget::Ids(ids) => ids.len() as u64,
+        }
+    }
+
+    fn get(&self, position: u64) -> i64 {
+        match self {
+            CrawlTarget::Range(start, _) => start + position as i64,
+            CrawlTarget::Ids(ids) => ids[position as usize],
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            CrawlTarget::Range(start, end) => format!("range:{}-{}", start, end),
+            CrawlTarget::Ids(ids) => {
+                // Every id is part of the key, so a different list never resumes this checkpoint
+                let mut hasher = Sha256::new();
+
+                for id in ids {
+                    hasher.update(id.to_le_bytes());
+                }
+
+                format!("ids:{}:{}", ids.len(), hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect::<String>())
+            }
+        }
+    }
+}
+
+enum FetchResult {
+    Fetched(Value),
+    Missing,
+    Failed,
+}
+
+impl PgcrCrawler {
+    /// A crawler with 8 requests in flight and 3 retries per PGCR
+    pub async fn new(client: &ApiClient) -> Self {
+        Self {
+            scraper: Arc::new(PgcrScraper::new(client).await),
+            concurrency: 8,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            checkpoint: None,
+            checkpoint_interval: 100,
+        }
+    }
+
+    /// How many PGCRs to download at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How many times to retry a failed request, waiting a little longer each time
+    pub fn with_retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Save progress to this file every interval PGCRs and resume from it
+    pub fn with_checkpoint(mut self, path: String, interval: u64) -> Self {
+        self.checkpoint = Some(PathBuf::from(path));
+        self.checkpoint_interval = interval.max(1);
+        self
+    }
+
+    /// Crawl every instance id from start up to, but not including, end
+    pub async fn crawl_range<S: PgcrSink>(&self, start: i64, end: i64, sink: &mut S) -> Result<CrawlReport> {
+        self.crawl(CrawlTarget::Range(start, end), sink).await
+    }
+
+    /// Crawl a list of instance ids, e.g. from activity history
+    pub async fn crawl_ids<S: PgcrSink>(&self, ids: Vec<i64>, sink: &mut S) -> Result<CrawlReport> {
+        self.crawl(CrawlTarget::Ids(ids), sink).await
+    }
+
+    /// Read the checkpoint file, if there is one
+    pub fn get_checkpoint(&self) -> Result<Option<CrawlReport>> {
+        match &self.checkpoint {
+            Some(path) if path.exists() => Ok(Some(serde_json::from_str::<CrawlReport>(fs::read_to_string(path)?.as_str())?)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn crawl<S: PgcrSink>(&self, target: CrawlTarget, sink: &mut S) -> Result<CrawlReport> {
+        let mut report = match self.get_checkpoint()? {
+            Some(report) if report.key == target.key() => report,
+            Some(report) => return Err(anyhow!("The checkpoint belongs to another crawl ({}), remove it to start over", report.key)),
+            None => CrawlReport {
+                key: target.key(),
+                total: target.len(),
+                ..Default::default()
+            },
+        };
+
+        if report.is_complete() {
+            return Ok(report);
+        }
+
+        let target = Arc::new(target);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, mut rx) = mpsc::channel::<(u64, i64, FetchResult)>(self.concurrency);
+
+        let producer = {
+            let target = target.clone();
+            let stop = stop.clone();
+            let scraper = self.scraper.clone();
+            let permits = Arc::new(Semaphore::new(self.concurrency));
+            let (start, retries, retry_delay) = (report.position, self.retries, self.retry_delay);
+            let completed = report.completed.iter().copied().collect::<BTreeSet<u64>>();
+
+            tokio::spawn(async move {
+                for position in start..target.len() {
+                    if completed.contains(&position) {
+                        continue;
+                    }
+
+                    let permit = match permits.clone().acquire_owned().await {
+                        Ok(permit) => permit,
+                        Err(_) => break,
+                    };
+
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    let id = target.get(position);
+                    let scraper = scraper.clone();
+                    let tx = tx.clone();
+
+                    tokio::spawn(async move {
+                        let result = PgcrCrawler::fetch(&scraper, id, retries, retry_delay).await;
+                        let _ = tx.send((position, id, result)).await;
+
+                        drop(permit);
+                    });
+                }
+            })
+        };
+
+        let mut progress = Progress::new(report.position, report.completed.clone());
+        let mut since_checkpoint = 0;
+
+        while let Some((position, id, result)) = rx.recv().await {
+            let accepted = match result {
+                FetchResult::Fetched(raw) => match PGCR::new(raw.clone()) {
+                    Ok(pgcr) => sink.accept(&pgcr, &raw).await.map(|_| report.fetched += 1),
+                    Err(_) => {
+                        report.failed.push(id);
+                        Ok(())
+                    }
+                },
+                FetchResult::Missing => {
+                    report.missing += 1;
+                    Ok(())
+                }
+                FetchResult::Failed => {
+                    report.failed.push(id);
+                    Ok(())
+                }
+            };
+
+            if let Err(e) = accepted {
+                // Only what was handled before this PGCR is checkpointed, so it is retried on resume
+                stop.store(true, Ordering::Relaxed);
+                producer.abort();
+
+                report.completed = progress.get_completed();
+                self.save_checkpoint(&report, sink).await?;
+                return Err(e);
+            }
+
+            report.position = progress.complete(position);
+            since_checkpoint += 1;
+
+            if since_checkpoint >= self.checkpoint_interval {
+                report.completed = progress.get_completed();
+                self.save_checkpoint(&report, sink).await?;
+                since_checkpoint = 0;
+            }
+
+            if report.is_complete() {
+                break;
+            }
+        }
+
+        report.completed = progress.get_completed();
+        report.failed.sort_unstable();
+        report.failed.dedup();
+        self.save_checkpoint(&report, sink).await?;
+
+        Ok(report)
+    }
+
+    async fn fetch(scraper: &PgcrScraper, id: i64, retries: u32, retry_delay: Duration) -> FetchResult {
+        for attempt in 0..=retries {
+            match scraper.try_get_pgcr_raw(id).await {
+                Ok(Some(raw)) => return FetchResult::Fetched(raw),
+                Ok(None) => return FetchResult::Missing,
+                Err(_) => {
+                    if attempt < retries {
+                        tokio::time::sleep(retry_delay * (attempt + 1)).await;
+                    }
+                }
+            }
+        }
+
+        FetchResult::Failed
+    }
+
+    async fn save_checkpoint<S: PgcrSink>(&self, report: &CrawlReport, sink: &mut S) -> Result<()> {
+        sink.flush().await?;
+
+        if let Some(path) = &self.checkpoint {
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, serde_json::to_string_pretty(report)?)?;
+            fs::rename(temp, path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Tracks the position everything before which has completed, as results arrive out of order
+struct Progress {
+    position: u64,
+    done: BTreeSet<u64>,
+}
+
+impl Progress {
+    /// Start at a position, with positions after it that were already completed
+    fn new(position: u64, completed: Vec<u64>) -> Self {
+        Self {
+            position,
+            done: completed.into_iter().filter(|p| *p >= position).collect(),
+        }
+    }
+
+    fn complete(&mut self, position: u64) -> u64 {
+        self.done.insert(position);
+
+        while self.done.remove(&self.position) {
+            self.position += 1;
+        }
+
+        self.position
+    }
+
+    /// The positions completed after the current position
+    fn get_completed(&self) -> Vec<u64> {
+        self.done.iter().copied().collect()
+    }
+}
+
+#[test]
+fn crawl_progress_out_of_order() {
+    let mut progress = Progress::new(10, vec![]);
+
+    assert_eq!(10, progress.complete(12));
+    assert_eq!(10, progress.complete(11));
+    assert_eq!(13, progress.complete(10));
+    assert_eq!(14, progress.complete(13));
+
+    // Resuming keeps what was completed past the position, so it isn't fetched and counted again
+    assert_eq!(14, progress.complete(16));
+    let mut resumed = Progress::new(progress.position, progress.get_completed());
+    assert_eq!(vec![16], resumed.get_completed());
+    assert_eq!(15, resumed.complete(14));
+    assert_eq!(17, resumed.complete(15));
+    assert!(resumed.get_completed().is_empty());
+
+    let target = CrawlTarget::Range(100, 105);
+    assert_eq!(5, target.len());
+    assert_eq!(103, target.get(3));
+    assert_eq!("range:100-105", target.key());
+    assert_eq!(0, CrawlTarget::Range(5, 1).len());
+
+    // Lists with the same length and endpoints are still different crawls
+    let ids = CrawlTarget::Ids(vec![1, 2, 3]);
+    assert_ne!(ids.key(), CrawlTarget::Ids(vec![1, 5, 3]).key());
+    assert_eq!(ids.key(), CrawlTarget::Ids(vec![1, 2, 3]).key());
+}
@@ -0,0 +1,309 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde_json::Value;
+use crate::api::ApiClient::ApiClient;
+use crate::api::activity::activity::{ActivityHistoryResponse, ActivityMode, PgcrScraper};
+use crate::api::DestinyAPI::URL_BASE;
+use crate::api::user::BungieUser::DestinyProfile;
+use crate::BungieUser;
+
+type HistoryStream<'a> = Pin<Box<dyn Stream<Item = Result<ActivityHistoryResponse>> + 'a>>;
+
+/// Which activities to get from a player's activity history
+///
+/// Bungie returns the history newest first, so paging stops as soon as an
+/// activity from before since() is reached.
+#[derive(Clone)]
+pub struct ActivityHistoryQuery {
+    modes: Vec<ActivityMode>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    max: Option<usize>,
+    page_size: usize,
+}
+
+impl Default for ActivityHistoryQuery {
+    fn default() -> Self {
+        Self {
+            modes: vec![],
+            since: None,
+            until: None,
+            max: None,
+            page_size: 250,
+        }
+    }
+}
+
+impl ActivityHistoryQuery {
+    /// Every activity of every mode
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only activities of this mode, can be given more than once
+    pub fn with_mode(mut self, mode: ActivityMode) -> Self {
+        self.modes.push(mode);
+        self
+    }
+
+    pub fn with_modes(mut self, modes: Vec<ActivityMode>) -> Self {
+        self.modes.extend(modes);
+        self
+    }
+
+    /// Only activities that started at or after this time
+    pub fn with_since(mut self, since: NaiveDateTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Only activities that started at or before this time
+    pub fn with_until(mut self, until: NaiveDateTime) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Stop after this many activities
+    pub fn with_max(mut self, max: usize) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// How many activities to request per page, Bungie allows up to 250
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.clamp(1, 250);
+        self
+    }
+
+    /// Whether an activity from this time should be returned, or None if paging can stop
+    fn accepts(&self, period: &NaiveDateTime) -> Option<bool> {
+        if self.since.map(|since| *period < since).unwrap_or(false) {
+            return None;
+        }
+
+        Some(self.until.map(|until| *period <= until).unwrap_or(true))
+    }
+
+    fn get_query(&self, page: usize) -> String {
+        let mut query = format!("?count={}&page={}", self.page_size, page);
+
+        if !self.modes.is_empty() {
+            query.push_str(format!("&mode={}", self.modes.iter().map(|m| m.get().to_string()).collect::<Vec<String>>().join(",")).as_str());
+        }
+
+        query
+    }
+}
+
+/// Paging through the history of one character
+struct CharacterHistory<'a> {
+    client: &'a ApiClient,
+    url: String,
+    query: ActivityHistoryQuery,
+    page: usize,
+    buffer: VecDeque<ActivityHistoryResponse>,
+    last_page: bool,
+    done: bool,
+    count: usize,
+}
+
+impl<'a> CharacterHistory<'a> {
+    async fn next(&mut self) -> Option<Result<ActivityHistoryResponse>> {
+        loop {
+            if self.done || self.query.max.map(|max| self.count >= max).unwrap_or(false) {
+                return None;
+            }
+
+            if let Some(activity) = self.buffer.pop_front() {
+                match self.query.accepts(&activity.period) {
+                    Some(true) => {
+                        self.count += 1;
+                        return Some(Ok(activity));
+                    }
+                    Some(false) => continue,
+                    None => {
+                        self.done = true;
+                        return None;
+                    }
+                }
+            }
+
+            if self.last_page {
+                self.done = true;
+                return None;
+            }
+
+            match self.fetch_page().await {
+                Ok(page) => {
+                    self.last_page = page.len() < self.query.page_size;
+                    self.buffer.extend(page);
+                    self.page += 1;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = Result<ActivityHistoryResponse>> + 'a {
+        stream::unfold(self, |mut history| async move {
+            history.next().await.map(|item| (item, history))
+        })
+    }
+
+    async fn fetch_page(&self) -> Result<Vec<ActivityHistoryResponse>> {
+        let val = serde_json::from_str::<Value>(self.client.get(format!("{}{}", self.url, self.query.get_query(self.page))).await?.as_str())?;
+
+        ApiClient::check_error_code(&val)?;
+
+        // Past the last page Bungie responds with an empty object instead of an empty list
+        match &val["Response"]["activities"] {
+            Value::Null => Ok(vec![]),
+            activities => serde_json::from_value::<Vec<ActivityHistoryResponse>>(activities.clone()).map_err(|e| anyhow!("Page {} of the activity history could not be read: {}", self.page, e)),
+        }
+    }
+}
+
+impl PgcrScraper {
+    /// Stream the activity history of a character, newest first
+    pub fn get_character_activity_history(&self, profile: &DestinyProfile, character_id: &str, query: &ActivityHistoryQuery) -> impl Stream<Item = Result<ActivityHistoryResponse>> + '_ {
+        let history = CharacterHistory {
+            client: &self.client,
+            url: format!("{}/Destiny2/{membershipType}/Account/{destinyMembershipId}/Character/{characterId}/Stats/Activities/", URL_BASE, membershipType = profile.platform, destinyMembershipId = profile.id, characterId = character_id),
+            query: query.clone(),
+            page: 0,
+            buffer: VecDeque::new(),
+            last_page: false,
+            done: false,
+            count: 0,
+        };
+
+        history.into_stream()
+    }
+
+    /// Stream the activity history of every character of a user, merged into one newest first
+    ///
+    /// If a character's history fails to load the error is returned in its place and
+    /// the other characters carry on.
+    pub async fn get_user_activity_history(&self, user: &BungieUser, query: &ActivityHistoryQuery) -> Result<impl Stream<Item = Result<ActivityHistoryResponse>> + '_> {
+        // Fused, so characters that have run out can be polled again on every step
+        let streams = user.get_characters(&self.client).await?.iter()
+            .map(|chara| Box::pin(self.get_character_activity_history(&user.primary, chara.characterId.as_str(), query).fuse()) as HistoryStream)
+            .collect::<Vec<HistoryStream>>();
+
+        Ok(merge_newest_first(streams, query.max))
+    }
+}
+
+/// Merge histories that are each newest first into one newest first, stopping after max activities
+///
+/// An error from one of the histories is returned in its place and the others carry on.
+fn merge_newest_first<'a>(streams: Vec<HistoryStream<'a>>, max: Option<usize>) -> impl Stream<Item = Result<ActivityHistoryResponse>> + 'a {
+    let heads = streams.iter().map(|_| None).collect::<Vec<Option<ActivityHistoryResponse>>>();
+
+    stream::unfold((streams, heads, 0), move |(mut streams, mut heads, count)| async move {
+        if max.map(|max| count >= max).unwrap_or(false) {
+            return None;
+        }
+
+        for (i, stream) in streams.iter_mut().enumerate() {
+            if heads[i].is_none() {
+                match stream.next().await {
+                    Some(Ok(activity)) => heads[i] = Some(activity),
+                    Some(Err(e)) => return Some((Err(e), (streams, heads, count))),
+                    None => {}
+                }
+            }
+        }
+
+        let newest = heads.iter().enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|h| (i, h.period)))
+            .max_by_key(|(_, period)| *period)
+            .map(|(i, _)| i)?;
+
+        let activity = heads[newest].take()?;
+
+        Some((Ok(activity), (streams, heads, count + 1)))
+    })
+}
+
+#[cfg(test)]
+fn test_activity(period: &str, instance_id: i64) -> ActivityHistoryResponse {
+    serde_json::from_value(serde_json::json!({
+        "period": period,
+        "activityDetails": {"referenceId": 1, "directorActivityHash": 1, "instanceId": instance_id.to_string(), "mode": 4, "modes": [4], "isPrivate": false, "membershipType": 3},
+        "values": {}
+    })).unwrap()
+}
+
+#[test]
+fn history_query_filters() {
+    let query = ActivityHistoryQuery::new()
+        .with_mode(ActivityMode::Raid)
+        .with_mode(ActivityMode::Dungeon)
+        .with_page_size(1000)
+        .with_since(NaiveDateTime::parse_from_str("2022-01-01T00:00:00Z", "%Y-%m-%dT%H:%M:%SZ").unwrap())
+        .with_until(NaiveDateTime::parse_from_str("2022-02-01T00:00:00Z", "%Y-%m-%dT%H:%M:%SZ").unwrap());
+
+    assert_eq!("?count=250&page=2&mode=4,82", query.get_query(2));
+
+    let time = |t: &str| NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%SZ").unwrap();
+
+    assert_eq!(Some(false), query.accepts(&time("2022-03-01T00:00:00Z")));
+    assert_eq!(Some(true), query.accepts(&time("2022-01-15T00:00:00Z")));
+    assert_eq!(None, query.accepts(&time("2021-12-31T23:59:59Z")));
+}
+
+#[tokio::test]
+async fn user_history_merge_since_max() {
+    let client = ApiClient::new("");
+    let time = |t: &str| NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%SZ").unwrap();
+
+    // A character whose only page has already been fetched
+    let character = |activities: Vec<ActivityHistoryResponse>, query: &ActivityHistoryQuery| {
+        let history = CharacterHistory {
+            client: &client,
+            url: String::new(),
+            query: query.clone(),
+            page: 1,
+            buffer: activities.into(),
+            last_page: true,
+            done: false,
+            count: 0,
+        };
+
+        Box::pin(history.into_stream().fuse()) as HistoryStream
+    };
+
+    let query = ActivityHistoryQuery::new()
+        .with_since(time("2022-01-02T00:00:00Z"))
+        .with_until(time("2022-01-10T00:00:00Z"));
+
+    let first = vec![test_activity("2022-01-12T00:00:00Z", 1), test_activity("2022-01-09T00:00:00Z", 2), test_activity("2022-01-05T00:00:00Z", 3), test_activity("2022-01-01T00:00:00Z", 4)];
+    let second = vec![test_activity("2022-01-08T00:00:00Z", 5), test_activity("2022-01-03T00:00:00Z", 6)];
+
+    let ids = |activities: Vec<Result<ActivityHistoryResponse>>| activities.into_iter().map(|a| a.unwrap().activityDetails.instanceId).collect::<Vec<String>>();
+
+    // Newest first across characters, without what is after until or before since
+    let merged = merge_newest_first(vec![character(first.clone(), &query), character(second.clone(), &query)], None).collect::<Vec<Result<ActivityHistoryResponse>>>().await;
+    assert_eq!(vec!["2", "5", "3", "6"], ids(merged));
+
+    let query = query.with_max(3);
+    let merged = merge_newest_first(vec![character(first, &query), character(second, &query)], query.max).collect::<Vec<Result<ActivityHistoryResponse>>>().await;
+    assert_eq!(vec!["2", "5", "3"], ids(merged));
+
+    // An error is passed on and the other character carries on
+    let failing = Box::pin(stream::iter(vec![Ok(test_activity("2022-01-04T00:00:00Z", 7)), Err(anyhow!("Throttled"))]).fuse()) as HistoryStream;
+    let merged = merge_newest_first(vec![failing, character(vec![test_activity("2022-01-06T00:00:00Z", 8)], &ActivityHistoryQuery::new())], None)
+        .collect::<Vec<Result<ActivityHistoryResponse>>>().await;
+
+    assert_eq!(3, merged.len());
+    assert_eq!("8", merged[0].as_ref().unwrap().activityDetails.instanceId);
+    assert_eq!("7", merged[1].as_ref().unwrap().activityDetails.instanceId);
+    assert!(merged[2].is_err());
+}
@@ -1 +1,6 @@
-pub mod activity;
\ No newline at end of file
+pub mod activity;
+pub mod resolver;
+pub mod crawler;
+pub mod history;
+pub mod sync;
+pub mod analytics;
\ No newline at end of file
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::api::activity::activity::{ActivityDetails, ActivityMode};
+use crate::api::manifest::definitions::DestinyActivityDefinition;
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestSource};
+use crate::enumize;
+
+// https://bungie-net.github.io/multi/schema_Destiny-DestinyActivityDifficultyTier.html
+enumize!(ActivityDifficultyTier, i32 => {
+    Trivial, 0,
+    Easy, 1,
+    Normal, 2,
+    Challenging, 3,
+    Hard, 4,
+    Brave, 5,
+    AlmostImpossible, 6,
+    Impossible, 7
+});
+
+/// Resolves activity hashes from PGCRs and activity history using the manifest
+///
+/// Every activity definition is read once when the resolver is built, so it should
+/// be kept around and rebuilt when the manifest is updated. Activities that are
+/// variants of each other, such as Vault of Glass and Vault of Glass: Master, share
+/// a canonical activity so they can be grouped together.
+pub struct ActivityResolver {
+    activities: HashMap<i64, ResolvedActivity>,
+    /// Every activity hash in a canonical group, keyed by the canonical hash
+    groups: HashMap<i64, Vec<i64>>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ResolvedActivity {
+    pub hash: i64,
+    pub name: String,
+    pub description: String,
+    pub activityTypeHash: i64,
+    /// e.g. Raid, Strike or Dungeon
+    pub activityType: Option<String>,
+    /// The DestinyActivityModeType of the activity, see get_mode()
+    pub modeType: Option<i32>,
+    pub modeName: Option<String>,
+    pub destinationHash: i64,
+    pub destination: Option<String>,
+    /// The difficulty tier, see get_difficulty()
+    pub tier: i32,
+    pub lightLevel: i32,
+    /// The largest fireteam that can launch it, if the definition says
+    #[serde(default)]
+    pub maxParty: Option<i32>,
+    /// The hash of the activity this is a variant of, or its own hash
+    pub canonicalHash: i64,
+    pub canonicalName: String,
+}
+
+impl ResolvedActivity {
+    /// The mode of the activity, None if ActivityMode doesn't have it yet
+    pub fn get_mode(&self) -> Option<ActivityMode> {
+        self.modeType.and_then(|m| ActivityMode::from(m as i16))
+    }
+
+    pub fn get_difficulty(&self) -> Option<ActivityDifficultyTier> {
+        ActivityDifficultyTier::from(self.tier)
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        self.hash == self.canonicalHash
+    }
+}
+
+impl ActivityResolver {
+    /// Build a resolver from a manifest
+    ///
+    /// Needs the activity, activity type, activity mode and destination tables,
+    /// which have to be loaded first when using a JsonManifest.
+    pub fn new<M: ManifestSource>(manifest: &M) -> Result<Self> {
+        let activities = manifest.get_all_raw(ManifestEntityType::ACTIVITY)?.into_iter()
+            .filter_map(|val| serde_json::from_value::<DestinyActivityDefinition>(val).ok())
+            .collect();
+
+        Ok(ActivityResolver::from_definitions(
+            activities,
+            ActivityResolver::names(manifest, ManifestEntityType::ACTIVITYTYPE)?,
+            ActivityResolver::names(manifest, ManifestEntityType::ACTIVITYMODE)?,
+            ActivityResolver::names(manifest, ManifestEntityType::DESTINATION)?,
+        ))
+    }
+
+    /// Build a resolver from activity definitions and the names of the types, modes and destinations they reference
+    pub fn from_definitions(definitions: Vec<DestinyActivityDefinition>, types: HashMap<i64, String>, modes: HashMap<i64, String>, destinations: HashMap<i64, String>) -> Self {
+        // Variants usually add a suffix to the original name, e.g. "Vault of Glass: Master",
+        // so a suffix is only dropped if an activity of the same type has the shorter name
+        let base_names = definitions.iter()
+            .map(|def| (ActivityResolver::base_name(def).to_lowercase(), def.activityTypeHash))
+            .collect::<HashSet<(String, i64)>>();
+
+        let mut activities = HashMap::new();
+        let mut groups: HashMap<(String, i64), Vec<(i64, bool)>> = HashMap::new();
+
+        for def in definitions {
+            let hash = ActivityResolver::normalize_hash(def.hash);
+            let base = ActivityResolver::base_name(&def);
+
+            let canonical_name = match base.rsplit_once(": ") {
+                Some((prefix, _)) if base_names.contains(&(prefix.to_lowercase(), def.activityTypeHash)) => prefix.to_string(),
+                _ => base.clone(),
+            };
+
+            if !canonical_name.is_empty() {
+                groups.entry((canonical_name.to_lowercase(), def.activityTypeHash)).or_default().push((hash, def.displayProperties.name == canonical_name));
+            }
+
+            activities.insert(hash, ResolvedActivity {
+                hash,
+                name: def.displayProperties.name.clone(),
+                description: def.displayProperties.description.clone(),
+                activityTypeHash: def.activityTypeHash,
+                activityType: types.get(&def.activityTypeHash).cloned(),
+                modeType: def.directActivityModeType,
+                modeName: def.directActivityModeHash.and_then(|m| modes.get(&ActivityResolver::normalize_hash(m)).cloned()),
+                destinationHash: def.destinationHash,
+                destination: destinations.get(&def.destinationHash).cloned(),
+                tier: def.tier,
+                lightLevel: def.activityLightLevel,
+                maxParty: def.matchmaking.as_ref().map(|m| m.maxParty).filter(|m| *m > 0),
+                canonicalHash: hash,
+                canonicalName: canonical_name,
+            });
+        }
+
+        let mut canonical_groups = HashMap::new();
+
+        for (_, mut members) in groups {
+            // Prefer the variant that is named exactly like the group, then the lowest hash
+            members.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+            let canonical = members[0].0;
+
+            for (hash, _) in &members {
+                if let Some(activity) = activities.get_mut(hash) {
+                    activity.canonicalHash = canonical;
+                }
+            }
+
+            canonical_groups.insert(canonical, members.into_iter().map(|(hash, _)| hash).collect());
+        }
+
+        Self {
+            activities,
+            groups: canonical_groups,
+        }
+    }
+
+    /// Resolve an activity hash, which may be signed or unsigned
+    pub fn resolve(&self, hash: i64) -> Option<&ResolvedActivity> {
+        self.activities.get(&ActivityResolver::normalize_hash(hash))
+    }
+
+    /// Resolve the activity of a PGCR or activity history entry
+    ///
+    /// The referenceId is the specific activity that was played, the directorActivityHash
+    /// is only used if it is unknown, as it can point at a playlist instead.
+    pub fn resolve_details(&self, details: &ActivityDetails) -> Option<&ResolvedActivity> {
+        self.resolve(details.referenceId).or_else(|| self.resolve(details.directorActivityHash))
+    }
+
+    /// Every variant of the canonical activity that the given activity belongs to
+    pub fn get_variants(&self, hash: i64) -> Vec<&ResolvedActivity> {
+        let canonical = match self.resolve(hash) {
+            Some(activity) => activity.canonicalHash,
+            None => return vec![],
+        };
+
+        match self.groups.get(&canonical) {
+            Some(hashes) => hashes.iter().filter_map(|h| self.activities.get(h)).collect(),
+            None => self.resolve(canonical).into_iter().collect(),
+        }
+    }
+
+    /// Find canonical activities by name, ignoring case
+    pub fn find(&self, name: &str) -> Vec<&ResolvedActivity> {
+        let name = name.to_lowercase();
+
+        let mut vec = self.activities.values()
+            .filter(|a| a.is_canonical() && a.canonicalName.to_lowercase() == name)
+            .collect::<Vec<&ResolvedActivity>>();
+        vec.sort_by_key(|a| a.hash);
+
+        vec
+    }
+
+    pub fn get_all(&self) -> Vec<&ResolvedActivity> {
+        self.activities.values().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.activities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.activities.is_empty()
+    }
+
+    fn names<M: ManifestSource>(manifest: &M, typ: ManifestEntityType) -> Result<HashMap<i64, String>> {
+        Ok(manifest.get_all_raw(typ)?.into_iter()
+            .filter_map(|val| Some((ActivityResolver::normalize_hash(val["hash"].as_i64()?), val["displayProperties"]["name"].as_str()?.to_string())))
+            .filter(|(_, name)| !name.is_empty())
+            .collect())
+    }
+
+    /// The name before any renaming, which is what variants of an activity have in common
+    fn base_name(def: &DestinyActivityDefinition) -> String {
+        match &def.originalDisplayProperties {
+            Some(original) if !original.name.is_empty() => original.name.clone(),
+            _ => def.displayProperties.name.clone(),
+        }
+    }
+
+    /// Hashes are unsigned, but PGCRs and the database can give them as signed numbers
+    fn normalize_hash(hash: i64) -> i64 {
+        hash as u32 as i64
+    }
+}
+
+#[test]
+fn resolver_groups_variants() {
+    let definitions = serde_json::from_str::<Vec<DestinyActivityDefinition>>(r#"[
+        {"hash": 3881495763, "displayProperties": {"name": "Vault of Glass"}, "activityTypeHash": 2043403989, "destinationHash": 290444260, "directActivityModeType": 4, "tier": 2},
+        {"hash": 1681562271, "displayProperties": {"name": "Vault of Glass: Master"}, "activityTypeHash": 2043403989, "destinationHash": 290444260, "directActivityModeType": 4, "tier": 5},
+        {"hash": 1485585878, "displayProperties": {"name": "Vault of Glass: Legend"}, "originalDisplayProperties": {"name": "Vault of Glass"}, "activityTypeHash": 2043403989},
+        {"hash": 910380154, "displayProperties": {"name": "Deep Stone Crypt"}, "activityTypeHash": 2043403989, "directActivityModeType": 4},
+        {"hash": 1, "displayProperties": {"name": "Vault of Glass"}, "activityTypeHash": 575572995}
+    ]"#).unwrap();
+
+    let resolver = ActivityResolver::from_definitions(definitions, HashMap::from([(2043403989, String::from("Raid"))]), HashMap::new(), HashMap::from([(290444260, String::from("Venus"))]));
+
+    let master = resolver.resolve(1681562271).unwrap();
+    assert_eq!(3881495763, master.canonicalHash);
+    assert_eq!("Vault of Glass", master.canonicalName);
+    assert_eq!(Some(String::from("Raid")), master.activityType);
+    assert_eq!(Some(String::from("Venus")), master.destination);
+    assert!(master.get_mode() == Some(ActivityMode::Raid));
+    assert!(master.get_difficulty() == Some(ActivityDifficultyTier::Brave));
+
+    // Signed hashes from the database resolve to the same activity
+    assert_eq!(3881495763, resolver.resolve(-413471533).unwrap().hash);
+    assert_eq!(3, resolver.get_variants(1485585878).len());
+    assert_eq!(1, resolver.get_variants(910380154).len());
+
+    // The same name under another activity type is a different activity
+    assert_eq!(2, resolver.find("vault of glass").len());
+    assert!(resolver.resolve(1).unwrap().is_canonical());
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use crate::api::activity::activity::{ActivityHistoryResponse, ActivityMode, PGCR, PgcrScraper};
+use crate::api::activity::history::ActivityHistoryQuery;
+use crate::api::user::BungieUser::DestinyProfile;
+use crate::api::Util::date_deserializer;
+use crate::BungieUser;
+
+/// The newest activity that has been synced for a character and mode
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Watermark {
+    #[serde(with = "date_deserializer")]
+    pub period: NaiveDateTime,
+    pub instanceId: i64,
+}
+
+impl Watermark {
+    /// Whether an activity is at or before the watermark, i.e. it has already been synced
+    pub fn is_reached(&self, period: NaiveDateTime, instance_id: i64) -> bool {
+        period < self.period || (period == self.period && instance_id <= self.instanceId)
+    }
+}
+
+/// Where synced activities and their watermarks are kept
+///
+/// Activities are given oldest first and the watermark is moved after each one, so
+/// a sync that is interrupted continues from the last activity that was stored.
+#[allow(async_fn_in_trait)]
+pub trait SyncStore {
+    async fn get_watermark(&self, key: &str) -> Result<Option<Watermark>>;
+
+    async fn set_watermark(&mut self, key: &str, watermark: Watermark) -> Result<()>;
+
+    /// Store a new activity, with its PGCR if the sync fetches them
+    async fn accept(&mut self, key: &str, activity: &ActivityHistoryResponse, pgcr: Option<&PGCR>) -> Result<()>;
+}
+
+/// Keeps everything in memory, e.g. to sync once and process the results
+#[derive(Default)]
+pub struct MemorySyncStore {
+    pub watermarks: HashMap<String, Watermark>,
+    pub activities: Vec<ActivityHistoryResponse>,
+    pub pgcrs: Vec<PGCR>,
+}
+
+impl SyncStore for MemorySyncStore {
+    async fn get_watermark(&self, key: &str) -> Result<Option<Watermark>> {
+        Ok(self.watermarks.get(key).cloned())
+    }
+
+    async fn set_watermark(&mut self, key: &str, watermark: Watermark) -> Result<()> {
+        self.watermarks.insert(key.to_string(), watermark);
+        Ok(())
+    }
+
+    async fn accept(&mut self, _key: &str, activity: &ActivityHistoryResponse, pgcr: Option<&PGCR>) -> Result<()> {
+        self.activities.push(activity.clone());
+
+        if let Some(pgcr) = pgcr {
+            self.pgcrs.push(pgcr.clone());
+        }
+
+        Ok(())
+    }
+}
+
+/// Downloads only the activities that are new since the last sync
+///
+/// A watermark is kept for every character and mode, and the activity history is
+/// only paged until it reaches the watermark. The first sync of a character gets
+/// their entire history.
+pub struct ActivitySync<'a> {
+    scraper: &'a PgcrScraper,
+    modes: Vec<ActivityMode>,
+    fetch_pgcrs: bool,
+}
+
+/// What a sync found
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct SyncReport {
+    pub characters: usize,
+    pub activities: usize,
+    pub pgcrs: usize,
+    /// Characters that failed, the rest of the sync carries on without them
+    pub errors: Vec<String>,
+    /// Activities Bungie has no readable PGCR for, with the reason, they are skipped and not synced again
+    #[serde(default)]
+    pub failed: Vec<(i64, String)>,
+}
+
+impl<'a> ActivitySync<'a> {
+    /// Sync every mode without fetching PGCRs
+    pub fn new(scraper: &'a PgcrScraper) -> Self {
+        Self {
+            scraper,
+            modes: vec![],
+            fetch_pgcrs: false,
+        }
+    }
+
+    /// Sync this mode, with its own watermark, can be given more than once
+    pub fn with_mode(mut self, mode: ActivityMode) -> Self {
+        self.modes.push(mode);
+        self
+    }
+
+    /// Also fetch the PGCR of every new activity
+    ///
+    /// Activities Bungie has no readable PGCR for are skipped and listed in SyncReport::failed,
+    /// any other error stops the sync so the activity is tried again next time
+    pub fn with_pgcrs(mut self, fetch_pgcrs: bool) -> Self {
+        self.fetch_pgcrs = fetch_pgcrs;
+        self
+    }
+
+    /// The key a watermark is stored under
+    pub fn get_key(membership_id: &str, character_id: &str, mode: ActivityMode) -> String {
+        format!("{}/{}/{}", membership_id, character_id, mode.get())
+    }
+
+    /// Sync every character of a user
+    pub async fn sync_user<S: SyncStore>(&self, user: &BungieUser, store: &mut S) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+
+        for chara in user.get_characters(&self.scraper.client).await? {
+            report.characters += 1;
+
+            if let Err(e) = self.sync_character(&user.primary, chara.characterId.as_str(), store, &mut report).await {
+                report.errors.push(format!("{}: {}", chara.characterId, e));
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Sync a character, adding what was found to the report
+    pub async fn sync_character<S: SyncStore>(&self, profile: &DestinyProfile, character_id: &str, store: &mut S, report: &mut SyncReport) -> Result<()> {
+        let modes = if self.modes.is_empty() { vec![ActivityMode::None] } else { self.modes.clone() };
+
+        for mode in modes {
+            let key = ActivitySync::get_key(profile.id.as_str(), character_id, mode);
+            let watermark = store.get_watermark(&key).await?;
+
+            let mut query = ActivityHistoryQuery::new().with_mode(mode);
+
+            if let Some(watermark) = &watermark {
+                query = query.with_since(watermark.period);
+            }
+
+            let history = self.scraper.get_character_activity_history(profile, character_id, &query);
+            self.sync_history(&key, watermark, history, store, report).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Store the activities of a history, newest first, that are past the watermark
+    async fn sync_history<S: SyncStore, H: Stream<Item = Result<ActivityHistoryResponse>>>(&self, key: &str, watermark: Option<Watermark>, history: H, store: &mut S, report: &mut SyncReport) -> Result<()> {
+        let mut new = vec![];
+        let mut history = Box::pin(history);
+
+        while let Some(activity) = history.next().await {
+            let activity = activity?;
+            let instance_id = activity.activityDetails.instanceId.parse::<i64>()?;
+
+            if watermark.as_ref().map(|w| w.is_reached(activity.period, instance_id)).unwrap_or(false) {
+                break;
+            }
+
+            new.push((instance_id, activity));
+        }
+
+        // Oldest first, so the watermark only moves past activities that were stored
+        for (instance_id, activity) in new.into_iter().rev() {
+            // Any error other than a missing or unreadable PGCR, e.g. throttling, stops before the watermark moves
+            let pgcr = match self.fetch_pgcrs {
+                true => match self.scraper.try_get_pgcr_raw(instance_id).await? {
+                    Some(raw) => match PGCR::new(raw) {
+                        Ok(pgcr) => Some(pgcr),
+                        Err(e) => {
+                            report.failed.push((instance_id, format!("The PGCR couldn't be parsed: {}", e)));
+                            None
+                        }
+                    },
+                    None => {
+                        report.failed.push((instance_id, "Bungie has no PGCR for this activity".to_string()));
+                        None
+                    }
+                },
+                false => None,
+            };
+
+            if !self.fetch_pgcrs || pgcr.is_some() {
+                store.accept(key, &activity, pgcr.as_ref()).await?;
+
+                report.activities += 1;
+                report.pgcrs += pgcr.is_some() as usize;
+            }
+
+            store.set_watermark(key, Watermark {
+                period: activity.period,
+                instanceId: instance_id,
+            }).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn sync_watermark_reached() {
+    let time = |t: &str| NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%SZ").unwrap();
+    let watermark = Watermark {
+        period: time("2022-01-01T20:00:00Z"),
+        instanceId: 100,
+    };
+
+    assert!(watermark.is_reached(time("2022-01-01T19:00:00Z"), 200));
+    assert!(watermark.is_reached(time("2022-01-01T20:00:00Z"), 100));
+    assert!(!watermark.is_reached(time("2022-01-01T20:00:00Z"), 101));
+    assert!(!watermark.is_reached(time("2022-01-02T20:00:00Z"), 50));
+
+    assert_eq!("1/2/4", ActivitySync::get_key("1", "2", ActivityMode::Raid));
+}
+
+#[tokio::test]
+async fn sync_history_past_watermark() {
+    let scraper = PgcrScraper::new(&crate::api::ApiClient::ApiClient::new("")).await;
+    let sync = ActivitySync::new(&scraper);
+    let mut store = MemorySyncStore::default();
+    let mut report = SyncReport::default();
+
+    let activity = |period: &str, instance_id: i64| serde_json::from_value::<ActivityHistoryResponse>(serde_json::json!({
+        "period": period,
+        "activityDetails": {"referenceId": 1, "directorActivityHash": 1, "instanceId": instance_id.to_string(), "mode": 4, "modes": [4], "isPrivate": false, "membershipType": 3},
+        "values": {}
+    })).unwrap();
+
+    let history = |activities: Vec<ActivityHistoryResponse>| futures_util::stream::iter(activities.into_iter().map(Ok));
+
+    // The first sync takes everything, oldest first, and leaves the watermark at the newest
+    let first = vec![activity("2022-01-03T00:00:00Z", 3), activity("2022-01-02T00:00:00Z", 2), activity("2022-01-01T00:00:00Z", 1)];
+    sync.sync_history("key", None, history(first), &mut store, &mut report).await.unwrap();
+
+    assert_eq!(vec!["1", "2", "3"], store.activities.iter().map(|a| a.activityDetails.instanceId.as_str()).collect::<Vec<&str>>());
+    assert_eq!(3, store.watermarks["key"].instanceId);
+
+    // The next sync stops at the watermark, including an activity that started at the same time
+    let watermark = store.watermarks.get("key").cloned();
+    let second = vec![activity("2022-01-04T00:00:00Z", 5), activity("2022-01-03T00:00:00Z", 4), activity("2022-01-03T00:00:00Z", 3), activity("2022-01-02T00:00:00Z", 2)];
+    sync.sync_history("key", watermark, history(second), &mut store, &mut report).await.unwrap();
+
+    assert_eq!(vec!["1", "2", "3", "4", "5"], store.activities.iter().map(|a| a.activityDetails.instanceId.as_str()).collect::<Vec<&str>>());
+    assert_eq!(5, store.watermarks["key"].instanceId);
+    assert_eq!(5, report.activities);
+
+    // A history that fails leaves the watermark where it was
+    let failing = futures_util::stream::iter(vec![Ok(activity("2022-01-05T00:00:00Z", 6)), Err(anyhow::anyhow!("Throttled"))]);
+    let watermark = store.watermarks.get("key").cloned();
+    assert!(sync.sync_history("key", watermark, failing, &mut store, &mut report).await.is_err());
+    assert_eq!(5, store.watermarks["key"].instanceId);
+
+    // So does a PGCR request that fails, without an API key or a connection here, rather than skipping the activity
+    let with_pgcrs = ActivitySync::new(&scraper).with_pgcrs(true);
+    let watermark = store.watermarks.get("key").cloned();
+    assert!(with_pgcrs.sync_history("key", watermark, history(vec![activity("2022-01-05T00:00:00Z", 6)]), &mut store, &mut report).await.is_err());
+    assert_eq!(5, store.watermarks["key"].instanceId);
+    assert_eq!(5, store.activities.len());
+    assert!(report.failed.is_empty());
+}
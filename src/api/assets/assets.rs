@@ -0,0 +1,264 @@
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::{anyhow, Result};
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+use crate::api::ApiClient::ApiClient;
+use crate::api::DestinyAPI::BUNGIE_BASE;
+
+/// A disk cache for images and other assets hosted on bungie.net
+///
+/// Accepts the relative paths found throughout the API as-is, such as
+/// DestinyCharacter::emblemPath, BnetMembership::icon_path, ClanDetail::avatarPath
+/// or DisplayProperties::icon. Each asset is stored under a hash of its URL, so
+/// it is only downloaded once and can be used offline afterwards. Assets are fetched
+/// without the API key or access token, and only from bungie.net. When the cache
+/// grows past its size limit the least recently used assets are removed.
+pub struct AssetCache {
+    client: ApiClient,
+    path: PathBuf,
+    max_size: u64,
+    max_asset_size: u64,
+}
+
+impl AssetCache {
+    /// Create a cache in the given directory, limited to 512 MB in total and 16 MB per asset
+    pub async fn new(client: &ApiClient, path: String) -> Result<Self> {
+        let path = PathBuf::from(path);
+        fs::create_dir_all(&path)?;
+
+        Ok(Self {
+            client: client.clone().await,
+            path,
+            max_size: 512 * 1024 * 1024,
+            max_asset_size: 16 * 1024 * 1024,
+        })
+    }
+
+    /// The total number of bytes the cache may use on disk
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Assets larger than this are refused instead of cached
+    pub fn with_max_asset_size(mut self, bytes: u64) -> Self {
+        self.max_asset_size = bytes;
+        self
+    }
+
+    /// Turn a relative bungie.net path into an absolute URL
+    ///
+    /// Absolute URLs are only accepted if they point at bungie.net or one of its subdomains
+    pub fn resolve_url(path: &str) -> Result<String> {
+        if !path.contains("://") && !path.starts_with("//") {
+            return Ok(match path.starts_with('/') {
+                true => format!("{}{}", BUNGIE_BASE, path),
+                false => format!("{}/{}", BUNGIE_BASE, path),
+            });
+        }
+
+        let url = Url::parse(path)?;
+        let host = url.host_str().unwrap_or_default();
+
+        if !matches!(url.scheme(), "http" | "https") || (host != "bungie.net" && !host.ends_with(".bungie.net")) {
+            return Err(anyhow!("The asset {} is not hosted on bungie.net", path));
+        }
+
+        Ok(url.to_string())
+    }
+
+    /// The name an asset is stored under, the SHA-256 of its URL plus the original extension
+    pub fn cache_key(path: &str) -> String {
+        let url = AssetCache::resolve_url(path).unwrap_or_else(|_| path.to_string());
+        let hash = Sha256::digest(url.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        // Query strings aren't part of the extension, e.g. /img/icon.png?v=2
+        let file = url.split(['?', '#']).next().unwrap_or_default();
+
+        match Path::new(file).extension().and_then(|e| e.to_str()) {
+            Some(ext) if !ext.is_empty() && ext.len() <= 5 => format!("{}.{}", hash, ext.to_lowercase()),
+            _ => hash,
+        }
+    }
+
+    /// Where an asset is, or would be, stored on disk
+    pub fn cached_path(&self, path: &str) -> PathBuf {
+        self.path.join(AssetCache::cache_key(path))
+    }
+
+    pub fn is_cached(&self, path: &str) -> bool {
+        self.cached_path(path).exists()
+    }
+
+    /// Get an asset from the cache without downloading it
+    pub fn get_cached(&self, path: &str) -> Option<Vec<u8>> {
+        let file = self.cached_path(path);
+        let bytes = fs::read(&file).ok()?;
+
+        AssetCache::touch(&file);
+
+        Some(bytes)
+    }
+
+    /// Get an asset, downloading it the first time
+    pub async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        if let Some(bytes) = self.get_cached(path) {
+            return Ok(bytes);
+        }
+
+        if path.trim().is_empty() {
+            return Err(anyhow!("The asset path is empty"));
+        }
+
+        let bytes = self.download(path).await?;
+        self.insert(path, &bytes)?;
+
+        Ok(bytes)
+    }
+
+    /// Download an asset without the API key or access token, stopping once it passes the size limit
+    async fn download(&self, path: &str) -> Result<Vec<u8>> {
+        let url = AssetCache::resolve_url(path)?;
+        let mut resp = Client::new().get(url.as_str()).send().await?.error_for_status()?;
+
+        if let Some(length) = resp.content_length() {
+            if length > self.max_asset_size {
+                return Err(anyhow!("The asset {} is {} bytes, which is more than the limit of {} bytes", path, length, self.max_asset_size));
+            }
+        }
+
+        let mut bytes = vec![];
+
+        while let Some(chunk) = resp.chunk().await? {
+            if (bytes.len() + chunk.len()) as u64 > self.max_asset_size {
+                return Err(anyhow!("The asset {} is more than the limit of {} bytes", path, self.max_asset_size));
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        if self.client.is_debug_enabled().await {
+            println!("GET {}", url);
+            println!("{} bytes", bytes.len());
+        }
+
+        Ok(bytes)
+    }
+
+    /// Get the location of an asset on disk, downloading it the first time
+    pub async fn get_file(&self, path: &str) -> Result<PathBuf> {
+        self.get(path).await?;
+
+        Ok(self.cached_path(path))
+    }
+
+    /// Store an asset, e.g. one that was downloaded elsewhere
+    pub fn insert(&self, path: &str, bytes: &[u8]) -> Result<()> {
+        let file = self.cached_path(path);
+
+        // Write through a temporary file so a reader never sees a partial image
+        let temp = file.with_extension("download");
+        fs::write(&temp, bytes)?;
+        fs::rename(&temp, &file)?;
+
+        self.prune()
+    }
+
+    /// The number of bytes the cache is using on disk
+    pub fn get_size(&self) -> Result<u64> {
+        Ok(self.entries()?.iter().map(|(_, size, _)| size).sum())
+    }
+
+    /// Remove the least recently used assets until the cache fits in its size limit
+    pub fn prune(&self) -> Result<()> {
+        let mut entries = self.entries()?;
+        let mut size = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+
+        if size <= self.max_size {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(_, _, used)| *used);
+
+        for (file, bytes, _) in entries {
+            if size <= self.max_size {
+                break;
+            }
+
+            if fs::remove_file(&file).is_ok() {
+                size -= bytes;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove every cached asset
+    pub fn clear(&self) -> Result<()> {
+        for (file, _, _) in self.entries()? {
+            fs::remove_file(file)?;
+        }
+
+        Ok(())
+    }
+
+    /// Every cached file with its size and when it was last used
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let mut vec = vec![];
+
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+
+            if metadata.is_file() && entry.path().extension().map(|e| e != "download").unwrap_or(true) {
+                vec.push((entry.path(), metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+            }
+        }
+
+        Ok(vec)
+    }
+
+    /// Mark a file as recently used, failures only make eviction less accurate
+    fn touch(file: &Path) {
+        if let Ok(f) = File::options().append(true).open(file) {
+            let _ = f.set_modified(SystemTime::now());
+        }
+    }
+}
+
+#[tokio::test]
+async fn asset_cache_limits() {
+    let dir = std::env::temp_dir().join("rustiny_asset_cache");
+    let _ = fs::remove_dir_all(&dir);
+
+    let cache = AssetCache::new(&ApiClient::new(""), dir.to_str().unwrap().to_string()).await.unwrap().with_max_size(10);
+
+    assert_eq!("https://www.bungie.net/img/profile/avatars/group/defaultGroup.png", AssetCache::resolve_url("/img/profile/avatars/group/defaultGroup.png").unwrap());
+    assert_eq!("https://images.bungie.net/a.png", AssetCache::resolve_url("https://images.bungie.net/a.png").unwrap());
+    assert!(AssetCache::resolve_url("https://example.com/a.png").is_err());
+    assert!(AssetCache::resolve_url("https://bungie.net.example.com/a.png").is_err());
+    assert!(AssetCache::resolve_url("//example.com/a.png").is_err());
+    assert!(cache.get("https://example.com/a.png").await.is_err());
+    assert_eq!(AssetCache::cache_key("/common/icon.JPG"), AssetCache::cache_key("https://www.bungie.net/common/icon.JPG"));
+    assert!(AssetCache::cache_key("/common/icon.JPG?v=2").ends_with(".jpg"));
+
+    cache.insert("/a.png", &[0; 4]).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    cache.insert("/b.png", &[1; 4]).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+
+    // Served from disk without a request, and now the most recently used
+    assert_eq!(vec![0; 4], cache.get("/a.png").await.unwrap());
+
+    // Going over the limit evicts /b.png, which was used least recently
+    cache.insert("/c.png", &[2; 4]).unwrap();
+    assert!(cache.is_cached("/a.png"));
+    assert!(!cache.is_cached("/b.png"));
+    assert!(cache.is_cached("/c.png"));
+    assert_eq!(8, cache.get_size().unwrap());
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1 @@
+pub mod assets;
\ No newline at end of file
@@ -8,6 +8,8 @@ use crate::api::ApiClient::ApiClient;
 use crate::api::Util::date_deserializer;
 use serde_json::Value;
 use crate::api::DestinyAPI::URL_BASE;
+use crate::api::manifest::definitions::{DestinyMilestoneDefinition, MilestoneRewardItem};
+use crate::api::manifest::manifest::{DisplayProperties, Manifest};
 use crate::api::user::BungieUser::{BnetMembership, DestinyProfile};
 use crate::BungieUser;
 
@@ -57,6 +59,14 @@ impl Clan {
 
         Ok(resp)
     }
+
+    /// Get the weekly rewards joined with their names, icons and items from the milestone definition
+    pub async fn get_weekly_rewards_resolved(&self, client: &ApiClient) -> Result<Vec<ClanRewardCategory>> {
+        let rewards = self.get_weekly_rewards(client).await?;
+        let milestone = Manifest::new(client.clone().await).get::<DestinyMilestoneDefinition>(rewards.milestoneHash).await?;
+
+        Ok(rewards.resolve(&milestone))
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -149,6 +159,105 @@ pub struct WeeklyReward {
     pub redeemed: bool,
 }
 
+impl WeeklyRewardResponse {
+    /// Join the reward state with the milestone definition, e.g. from get::<DestinyMilestoneDefinition>(milestoneHash)
+    ///
+    /// Categories and entries the definition doesn't know about are kept, just without names
+    pub fn resolve(&self, milestone: &DestinyMilestoneDefinition) -> Vec<ClanRewardCategory> {
+        let mut categories = vec![];
+
+        for rewards in &self.rewards {
+            let category = milestone.get_reward_category(rewards.rewardCategoryHash);
+
+            let mut entries = vec![];
+
+            for reward in &rewards.entries {
+                let entry = category.and_then(|c| c.get_entry(reward.rewardEntryHash)).or_else(|| milestone.get_reward_entry(reward.rewardEntryHash));
+
+                entries.push(ClanReward {
+                    rewardEntryHash: reward.rewardEntryHash,
+                    identifier: entry.map(|e| e.rewardEntryIdentifier.clone()).unwrap_or_default(),
+                    displayProperties: entry.map(|e| e.displayProperties.clone()).unwrap_or_default(),
+                    items: entry.map(|e| e.items.clone()).unwrap_or_default(),
+                    order: entry.map(|e| e.order).unwrap_or(i32::MAX),
+                    earned: reward.earned,
+                    redeemed: reward.redeemed,
+                });
+            }
+
+            entries.sort_by_key(|e| e.order);
+
+            categories.push(ClanRewardCategory {
+                categoryHash: rewards.rewardCategoryHash,
+                identifier: category.map(|c| c.categoryIdentifier.clone()).unwrap_or_default(),
+                displayProperties: category.map(|c| c.displayProperties.clone()).unwrap_or_default(),
+                order: category.map(|c| c.order).unwrap_or(i32::MAX),
+                entries,
+            });
+        }
+
+        categories.sort_by_key(|c| c.order);
+
+        categories
+    }
+}
+
+/// A clan reward category, such as the current or previous week, with its rewards
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClanRewardCategory {
+    pub categoryHash: i64,
+    /// e.g. current_week
+    pub identifier: String,
+    pub displayProperties: DisplayProperties,
+    pub order: i32,
+    pub entries: Vec<ClanReward>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClanReward {
+    pub rewardEntryHash: i64,
+    /// e.g. nightfall or raid
+    pub identifier: String,
+    pub displayProperties: DisplayProperties,
+    pub items: Vec<MilestoneRewardItem>,
+    pub order: i32,
+    pub earned: bool,
+    pub redeemed: bool,
+}
+
+#[test]
+fn weekly_rewards_resolve() {
+    let milestone = serde_json::from_str::<DestinyMilestoneDefinition>(r#"{
+        "hash": 4253138191,
+        "rewards": {"1064137897": {"categoryHash": 1064137897, "categoryIdentifier": "current_week", "displayProperties": {"name": "This Week"}, "order": 0,
+            "rewardEntries": {
+                "3789021730": {"rewardEntryHash": 3789021730, "rewardEntryIdentifier": "nightfall", "displayProperties": {"name": "Nightfall", "icon": "/nf.png"}, "order": 1, "items": [{"itemHash": 1, "quantity": 1}]},
+                "2043403989": {"rewardEntryHash": 2043403989, "rewardEntryIdentifier": "raid", "displayProperties": {"name": "Raid"}, "order": 0}
+            }
+        }}
+    }"#).unwrap();
+
+    let state = serde_json::from_str::<WeeklyRewardResponse>(r#"{"milestoneHash": 4253138191, "rewards": [
+        {"rewardCategoryHash": 1064137897, "entries": [
+            {"rewardEntryHash": 3789021730, "earned": true, "redeemed": false},
+            {"rewardEntryHash": 2043403989, "earned": false, "redeemed": false},
+            {"rewardEntryHash": 12345, "earned": false, "redeemed": false}
+        ]},
+        {"rewardCategoryHash": 4258746474, "entries": []}
+    ]}"#).unwrap();
+
+    let categories = state.resolve(&milestone);
+
+    assert_eq!("current_week", categories[0].identifier);
+    assert_eq!(vec!["Raid", "Nightfall", ""], categories[0].entries.iter().map(|e| e.displayProperties.name.as_str()).collect::<Vec<&str>>());
+    assert!(categories[0].entries[1].earned);
+    assert_eq!(1, categories[0].entries[1].items.len());
+
+    // Unknown categories are kept instead of failing
+    assert_eq!(4258746474, categories[1].categoryHash);
+    assert_eq!("", categories[1].displayProperties.name);
+}
+
 /*
 /Platform/GroupV2/3074427/
 
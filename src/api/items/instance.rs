@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestSource};
+#[cfg(test)]
+use crate::api::manifest::manifest::TestSource;
+
+/// Everything the item endpoint returns about a single instanced item
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemInstanceDetails {
+    /// The character holding the item, None if it is in the vault or profile inventory
+    pub characterId: Option<String>,
+    pub instance: Option<ItemInstance>,
+    #[serde(default)]
+    pub stats: HashMap<String, ItemStat>,
+    #[serde(default)]
+    pub sockets: Vec<ItemSocket>,
+    /// The plugs that can be inserted into each socket, keyed by socket index
+    #[serde(default)]
+    pub reusablePlugs: HashMap<String, Vec<ItemReusablePlug>>,
+    /// Progress on objectives attached to plugs such as kill trackers, keyed by plug hash
+    #[serde(default)]
+    pub plugObjectives: HashMap<String, Vec<ItemObjective>>,
+    #[serde(default)]
+    pub perks: Vec<ItemPerk>,
+    #[serde(default)]
+    pub objectives: Vec<ItemObjective>,
+}
+
+impl ItemInstanceDetails {
+    /// Flatten the "Response" of the item endpoint, skipping any component that wasn't returned
+    pub fn new(resp: &Value) -> Result<Self> {
+        let mut map = Map::new();
+
+        let components = [
+            ("characterId", &resp["characterId"]),
+            ("instance", &resp["instance"]["data"]),
+            ("stats", &resp["stats"]["data"]["stats"]),
+            ("sockets", &resp["sockets"]["data"]["sockets"]),
+            ("reusablePlugs", &resp["reusablePlugs"]["data"]["plugs"]),
+            ("plugObjectives", &resp["plugObjectives"]["data"]["objectivesPerPlug"]),
+            ("perks", &resp["perks"]["data"]["perks"]),
+            ("objectives", &resp["objectives"]["data"]["objectives"]),
+        ];
+
+        for (key, val) in components {
+            if !val.is_null() {
+                map.insert(key.to_string(), val.clone());
+            }
+        }
+
+        Ok(serde_json::from_value::<ItemInstanceDetails>(Value::Object(map))?)
+    }
+
+    /// Fill in the name of every socket's plug from a local manifest
+    ///
+    /// Plugs the manifest doesn't know yet, e.g. right after a patch, are left without a name
+    pub fn resolve_plug_names<M: ManifestSource>(&mut self, manifest: &M) -> Result<()> {
+        let mut names: HashMap<i64, Option<String>> = HashMap::new();
+
+        for socket in self.sockets.iter_mut() {
+            if let Some(hash) = socket.plugHash {
+                socket.plugName = match names.entry(hash) {
+                    Entry::Occupied(entry) => entry.get().clone(),
+                    Entry::Vacant(entry) => {
+                        let def = manifest.find_raw(ManifestEntityType::INVENTORYITEM, hash)?;
+                        entry.insert(def.map(|d| d["displayProperties"]["name"].as_str().unwrap_or_default().to_string())).clone()
+                    }
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that a plug can go into this item before asking Bungie to insert it
+    ///
+    /// The plug must be one of the socket's reusable plugs if the socket has any,
+    /// and the item must have enough unused energy once the current plug is removed.
+    pub fn validate_plug(&self, plug: &SocketPlug, energy_cost: i32, replaced_energy_cost: i32) -> Result<()> {
+        let socket = self.sockets.get(plug.socketIndex as usize).ok_or_else(|| anyhow!("Item has no socket at index {}", plug.socketIndex))?;
+
+        if !socket.isEnabled {
+            return Err(anyhow!("Socket {} is not enabled", plug.socketIndex));
+        }
+
+        if let Some(plugs) = self.reusablePlugs.get(&plug.socketIndex.to_string()) {
+            match plugs.iter().find(|p| p.plugItemHash == plug.plugItemHash) {
+                Some(p) if p.canInsert && p.enabled => {}
+                Some(_) => return Err(anyhow!("Plug {} cannot currently be inserted into socket {}", plug.plugItemHash, plug.socketIndex)),
+                None => return Err(anyhow!("Plug {} is not available for socket {}", plug.plugItemHash, plug.socketIndex)),
+            }
+        }
+
+        if let Some(energy) = self.instance.as_ref().and_then(|i| i.energy.as_ref()) {
+            let available = energy.energyUnused + replaced_energy_cost;
+
+            if energy_cost > available {
+                return Err(anyhow!("Plug {} costs {} energy but only {} is available", plug.plugItemHash, energy_cost, available));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A plug to insert into one of an item's sockets
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SocketPlug {
+    pub socketIndex: i32,
+    /// 0 for the default socket array, 1 for intrinsics
+    pub socketArrayType: i32,
+    pub plugItemHash: i64,
+}
+
+impl SocketPlug {
+    pub fn new(socket_index: i32, plug_item_hash: i64) -> Self {
+        Self {
+            socketIndex: socket_index,
+            socketArrayType: 0,
+            plugItemHash: plug_item_hash,
+        }
+    }
+}
+
+/// Component 300 - ItemInstances
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemInstance {
+    pub damageType: i32,
+    pub damageTypeHash: Option<i64>,
+    pub primaryStat: Option<ItemStat>,
+    pub itemLevel: i32,
+    pub quality: i32,
+    pub isEquipped: bool,
+    pub canEquip: bool,
+    pub equipRequiredLevel: i32,
+    pub cannotEquipReason: i32,
+    pub breakerType: Option<i32>,
+    pub breakerTypeHash: Option<i64>,
+    pub energy: Option<ItemEnergy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemEnergy {
+    pub energyTypeHash: i64,
+    pub energyType: i32,
+    pub energyCapacity: i32,
+    pub energyUsed: i32,
+    pub energyUnused: i32,
+}
+
+/// Component 304 - ItemStats
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemStat {
+    pub statHash: i64,
+    pub value: i32,
+}
+
+/// Component 305 - ItemSockets
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemSocket {
+    pub plugHash: Option<i64>,
+    pub isEnabled: bool,
+    pub isVisible: bool,
+    /// The display name of the plug, filled in from the manifest
+    #[serde(default)]
+    pub plugName: Option<String>,
+}
+
+/// Component 310 - ItemReusablePlugs
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemReusablePlug {
+    pub plugItemHash: i64,
+    pub canInsert: bool,
+    pub enabled: bool,
+}
+
+/// Components 301 and 309 - ItemObjectives and ItemPlugObjectives
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemObjective {
+    pub objectiveHash: i64,
+    pub progress: Option<i64>,
+    pub completionValue: i64,
+    pub complete: bool,
+    pub visible: bool,
+}
+
+/// Component 302 - ItemPerks
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemPerk {
+    pub perkHash: i64,
+    pub iconPath: Option<String>,
+    pub isActive: bool,
+    pub visible: bool,
+}
+
+#[test]
+fn item_instance_details_skip_missing_components() {
+    let resp = serde_json::from_str::<Value>(r#"{
+        "characterId": "2305843009300000000",
+        "instance": { "data": { "damageType": 3, "damageTypeHash": 2303181850, "primaryStat": { "statHash": 1480404414, "value": 1810 }, "itemLevel": 181, "quality": 0,
+            "isEquipped": true, "canEquip": true, "equipRequiredLevel": 50, "cannotEquipReason": 0 } },
+        "sockets": { "data": { "sockets": [ { "plugHash": 3250034553, "isEnabled": true, "isVisible": true }, { "isEnabled": false, "isVisible": false } ] } },
+        "perks": { "privacy": 2 }
+    }"#).unwrap();
+
+    let details = ItemInstanceDetails::new(&resp).unwrap();
+    assert_eq!(1810, details.instance.unwrap().primaryStat.unwrap().value);
+    assert_eq!(2, details.sockets.len());
+    assert!(details.sockets[1].plugHash.is_none());
+    assert!(details.perks.is_empty());
+    assert!(details.stats.is_empty());
+}
+
+#[test]
+fn validate_plug_energy_and_reusable_plugs() {
+    let details = serde_json::from_str::<ItemInstanceDetails>(r#"{
+        "instance": { "damageType": 0, "itemLevel": 181, "quality": 0, "isEquipped": false, "canEquip": true, "equipRequiredLevel": 50, "cannotEquipReason": 0,
+            "energy": { "energyTypeHash": 0, "energyType": 0, "energyCapacity": 10, "energyUsed": 8, "energyUnused": 2 } },
+        "sockets": [ { "plugHash": 1, "isEnabled": true, "isVisible": true }, { "plugHash": 2, "isEnabled": true, "isVisible": true } ],
+        "reusablePlugs": { "1": [ { "plugItemHash": 2, "canInsert": true, "enabled": true }, { "plugItemHash": 3, "canInsert": false, "enabled": true } ] }
+    }"#).unwrap();
+
+    assert!(details.validate_plug(&SocketPlug::new(0, 10), 3, 1).is_ok());
+    assert!(details.validate_plug(&SocketPlug::new(0, 10), 4, 1).is_err());
+    assert!(details.validate_plug(&SocketPlug::new(1, 3), 0, 0).is_err());
+    assert!(details.validate_plug(&SocketPlug::new(1, 4), 0, 0).is_err());
+    assert!(details.validate_plug(&SocketPlug::new(5, 2), 0, 0).is_err());
+}
+
+#[test]
+fn resolve_plug_names_from_manifest() {
+    let mut details = serde_json::from_str::<ItemInstanceDetails>(r#"{
+        "sockets": [ { "plugHash": 3250034553, "isEnabled": true, "isVisible": true }, { "isEnabled": false, "isVisible": false }, { "plugHash": 3250034553, "isEnabled": true, "isVisible": true } ]
+    }"#).unwrap();
+    let manifest = TestSource::new(vec![(ManifestEntityType::INVENTORYITEM, vec![serde_json::json!({"hash": 3250034553_i64, "displayProperties": {"name": "Outlaw"}})])]);
+
+    details.resolve_plug_names(&manifest).unwrap();
+    assert_eq!(Some("Outlaw".to_string()), details.sockets[0].plugName);
+    assert!(details.sockets[1].plugName.is_none());
+    assert_eq!(Some("Outlaw".to_string()), details.sockets[2].plugName);
+
+    // A plug the manifest doesn't know yet only loses its own name
+    details.sockets[1].plugHash = Some(1);
+    details.resolve_plug_names(&manifest).unwrap();
+    assert!(details.sockets[1].plugName.is_none());
+    assert_eq!(Some("Outlaw".to_string()), details.sockets[2].plugName);
+}
@@ -1,8 +1,21 @@
-use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use crate::api::ApiClient::ApiClient;
 use crate::api::DestinyAPI::URL_BASE;
-use crate::DestinyCharacter;
+use crate::api::manifest::definitions::DestinyInventoryItemDefinition;
+use crate::api::items::instance::{ItemInstanceDetails, SocketPlug};
+use crate::api::oauth::awa;
+use crate::api::oauth::awa::AwaType;
+use crate::api::manifest::manifest::{DisplayProperties, Manifest, ManifestEntityType, ManifestSource};
+use crate::api::user::BungieUser::DestinyComponentType;
+use crate::{BungieUser, DestinyCharacter, enumize, map};
+
+/// The most pages Item::search() will follow
+pub const MAX_SEARCH_PAGES: i32 = 20;
 
 #[derive(Debug, Clone)]
 pub struct Item {
@@ -14,41 +27,386 @@ impl Item {
     Use manifest to get the item under DestinyInventoryItemDefinition
     **/
     pub fn new(hash: i64) -> Self {
-        todo!()
+        Self {
+            hash,
+        }
+    }
+
+    /// The energy a plug costs to insert, 0 for plugs without an energy cost
+    pub async fn get_energy_cost(manifest: &Manifest, plug_item_hash: i64) -> Result<i32> {
+        let def = manifest.manifest_value(ManifestEntityType::INVENTORYITEM, plug_item_hash).await?;
+
+        Ok(def["plug"]["energyCost"]["energyCost"].as_i64().unwrap_or(0) as i32)
+    }
+
+    /// Get the instance, stats, sockets, plugs, perks and objectives of an instanced item
+    ///
+    /// Each socket's plug name is looked up in the given manifest, without any further
+    /// requests. Some components are private and are left empty unless the client has
+    /// an access token for this user.
+    pub async fn get_instance<M: ManifestSource>(client: &ApiClient, manifest: &M, membership_type: i16, membership_id: &str, item_instance_id: i64) -> Result<ItemInstanceDetails> {
+        let mut details = Item::get_instance_details(client, membership_type, membership_id, item_instance_id).await?;
+        details.resolve_plug_names(manifest)?;
+
+        Ok(details)
+    }
+
+    /// Get the details of an instanced item like get_instance(), leaving plug names empty
+    pub async fn get_instance_details(client: &ApiClient, membership_type: i16, membership_id: &str, item_instance_id: i64) -> Result<ItemInstanceDetails> {
+        let components = vec![DestinyComponentType::ItemInstances, DestinyComponentType::ItemObjectives, DestinyComponentType::ItemPerks, DestinyComponentType::ItemStats,
+                              DestinyComponentType::ItemSockets, DestinyComponentType::ItemPlugObjectives, DestinyComponentType::ItemReusablePlugs];
+        let url = format!("{}/Destiny2/{membershipType}/Profile/{destinyMembershipId}/Item/{itemInstanceId}/?components={components}", URL_BASE, membershipType = membership_type,
+                          destinyMembershipId = membership_id, itemInstanceId = item_instance_id, components = DestinyComponentType::join(components));
+
+        let resp = client.get_parse::<Value>(url, true).await?;
+
+        ItemInstanceDetails::new(&resp)
+    }
+
+    /// Search every item definition by name, following pages until there are no more results
+    ///
+    /// At most MAX_SEARCH_PAGES pages are requested, use search_page() to go further
+    pub async fn search(client: &ApiClient, search: &str) -> Result<Vec<ItemSearchResult>> {
+        let mut vec = vec![];
+
+        for page in 0..MAX_SEARCH_PAGES {
+            let resp = Item::search_page(client, search, page).await?;
+
+            if resp.results.is_empty() {
+                break;
+            }
+
+            vec.extend(resp.results);
+
+            if !resp.hasMore {
+                break;
+            }
+        }
+
+        Ok(vec)
     }
 
-    pub async fn search(client: ApiClient, search: String) -> Result<Vec<Self>> {
-        let resp = client.get(format!("{base}/Destiny2/Armory/Search/DestinyInventoryItemDefinition/{search}/", base = URL_BASE)).await?;
+    /// Get a single page of item search results, starting at page 0
+    pub async fn search_page(client: &ApiClient, search: &str, page: i32) -> Result<ItemSearchPage> {
+        let mut url = Url::parse(format!("{base}/Destiny2/Armory/Search/DestinyInventoryItemDefinition/", base = URL_BASE).as_str())?;
+        url.path_segments_mut().map_err(|_| anyhow!("Search URL cannot be a base"))?.pop_if_empty().push(search).push("");
+
+        let page = page.to_string();
+        let resp = client.get_parse_params::<Value>(url.to_string(), true, map!("page" => page.as_str())).await?;
 
-        todo!()
+        Ok(ItemSearchPage {
+            results: serde_json::from_value::<Vec<ItemSearchResult>>(resp["results"]["results"].clone())?,
+            totalResults: resp["results"]["totalResults"].as_i64().unwrap_or(0) as i32,
+            hasMore: resp["results"]["hasMore"].as_bool().unwrap_or(false),
+            suggestedWords: serde_json::from_value::<Vec<String>>(resp["suggestedWords"].clone()).unwrap_or_default(),
+        })
     }
 }
 
+pub struct ItemSearchPage {
+    pub results: Vec<ItemSearchResult>,
+    pub totalResults: i32,
+    pub hasMore: bool,
+    pub suggestedWords: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ItemSearchResult {
+    pub hash: i64,
+    pub entityType: String,
+    pub displayProperties: DisplayProperties,
+    pub weight: f64,
+    /// e.g. "Auto Rifle", only filled in once the result is hydrated
+    #[serde(default)]
+    pub itemTypeDisplayName: Option<String>,
+    /// e.g. "Legendary", only filled in once the result is hydrated
+    #[serde(default)]
+    pub tierTypeName: Option<String>,
+}
+
+impl ItemSearchResult {
+    pub fn get_item(&self) -> Item {
+        Item::new(self.hash)
+    }
+
+    /// Look up the full definition of this item, filling in its item type and tier
+    pub async fn hydrate(&mut self, manifest: &Manifest) -> Result<DestinyInventoryItemDefinition> {
+        let def = manifest.get::<DestinyInventoryItemDefinition>(self.hash).await?;
+
+        self.itemTypeDisplayName = Some(def.itemTypeDisplayName.clone());
+        self.tierTypeName = def.inventory.as_ref().and_then(|i| i.tierTypeName.clone());
+
+        Ok(def)
+    }
+}
+
+/// An item as it appears in the profile or character inventory components
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DestinyItemComponent {
+    pub itemHash: i64,
+    pub itemInstanceId: Option<String>,
+    pub quantity: i32,
+    pub bucketHash: i64,
+    pub location: i8,
+    pub transferStatus: i8,
+    pub lockable: bool,
+    pub state: i32,
+}
+
 pub struct InventoryItem {
     pub item: Item,
-    pub character_owner: DestinyCharacter,
+    /// The character holding this item, None if it is in the vault
+    pub character_owner: Option<DestinyCharacter>,
+    pub membershipType: i8,
     pub instanceID: i64,
+    pub bucketHash: i64,
     pub isEquippable: bool,
+    pub isEquipped: bool,
     pub stackSize: i32,
 }
 
 impl InventoryItem {
+    pub fn new(component: DestinyItemComponent, character_owner: Option<DestinyCharacter>, membership_type: i8, is_equipped: bool) -> Self {
+        Self {
+            item: Item { hash: component.itemHash },
+            character_owner,
+            membershipType: membership_type,
+            instanceID: component.itemInstanceId.and_then(|id| id.parse::<i64>().ok()).unwrap_or(0),
+            bucketHash: component.bucketHash,
+            isEquippable: InventoryBucket::from(component.bucketHash).is_some(),
+            isEquipped: is_equipped,
+            stackSize: component.quantity,
+        }
+    }
+
+    /// Get every item this user owns, across all of their characters and the vault
+    ///
+    /// Requires the client to have an access token for this user
+    pub async fn get_all(client: &ApiClient, user: &BungieUser) -> Result<Vec<InventoryItem>> {
+        let resp = user.get_profile_components(client, vec![DestinyComponentType::Characters, DestinyComponentType::ProfileInventories,
+                                                             DestinyComponentType::CharacterInventories, DestinyComponentType::CharacterEquipment]).await?;
+        let membership_type = user.primary.platform as i8;
+
+        let mut vec = vec![];
+
+        if let Some(map) = resp["characters"]["data"].as_object() {
+            for (id, chara) in map {
+                let chara = DestinyCharacter::new_with_user(chara.clone(), user.clone())?;
+
+                for i in serde_json::from_value::<Vec<DestinyItemComponent>>(resp["characterInventories"]["data"][id]["items"].clone())? {
+                    vec.push(InventoryItem::new(i, Some(chara.clone()), membership_type, false));
+                }
+
+                for i in serde_json::from_value::<Vec<DestinyItemComponent>>(resp["characterEquipment"]["data"][id]["items"].clone())? {
+                    vec.push(InventoryItem::new(i, Some(chara.clone()), membership_type, true));
+                }
+            }
+        }
+
+        for i in serde_json::from_value::<Vec<DestinyItemComponent>>(resp["profileInventory"]["data"]["items"].clone())? {
+            vec.push(InventoryItem::new(i, None, membership_type, false));
+        }
+
+        Ok(vec)
+    }
+
     pub fn get_json(&self, move_to_vault: bool) -> Value {
+        self.get_json_for_character(self.character_owner.as_ref().map(|c| c.characterId.clone()).unwrap_or_default(), move_to_vault)
+    }
+
+    /// The transfer body, using the given character as either the source (to vault) or destination (from vault)
+    fn get_json_for_character(&self, character_id: String, move_to_vault: bool) -> Value {
         json!({
             "itemReferenceHash": self.item.hash,
             "stackSize": self.stackSize,
             "transferToVault": move_to_vault,
             "itemId": self.instanceID,
-            "characterId": self.character_owner.characterId.parse::<i64>().unwrap(),
-            "membershipType": self.character_owner.membershipType,
+            "characterId": character_id.parse::<i64>().unwrap_or(0),
+            "membershipType": self.membershipType,
         })
     }
+
+    /// Get the full instance details of this item, see Item::get_instance()
+    pub async fn get_instance<M: ManifestSource>(&self, client: &ApiClient, manifest: &M, membership_id: &str) -> Result<ItemInstanceDetails> {
+        Item::get_instance(client, manifest, self.membershipType as i16, membership_id, self.instanceID).await
+    }
+
+    /// Get the instance details of this item without plug names, see Item::get_instance_details()
+    pub async fn get_instance_details(&self, client: &ApiClient, membership_id: &str) -> Result<ItemInstanceDetails> {
+        Item::get_instance_details(client, self.membershipType as i16, membership_id, self.instanceID).await
+    }
+
+    /// Insert a plug that doesn't cost anything, such as a mod, shader, ornament or subclass fragment
+    ///
+    /// The plug is checked against the item's sockets, reusable plugs and energy first
+    pub async fn insert_plug_free(&self, client: &ApiClient, plug: SocketPlug) -> Result<()> {
+        let owner = self.validate_plug(client, &plug).await?;
+
+        let body = json!({
+            "plug": plug,
+            "itemId": self.instanceID,
+            "characterId": owner,
+            "membershipType": self.membershipType,
+        });
+
+        client.post_action(format!("{}/Destiny2/Actions/Items/InsertSocketPlugFree/", URL_BASE), body.to_string()).await?;
+
+        Ok(())
+    }
+
+    /// Insert any plug, including ones that cost materials
+    ///
+    /// This sends an approval request to the user's companion app and waits up to
+    /// the timeout for them to accept it before inserting the plug.
+    pub async fn insert_plug(&self, client: &ApiClient, plug: SocketPlug, timeout: Duration) -> Result<()> {
+        let owner = self.validate_plug(client, &plug).await?;
+
+        let awa = awa::initialize(client, AwaType::InsertPlugs, self.instanceID, self.membershipType, owner).await?;
+        let token = awa::wait_for_action_token(client, awa.correlationId.as_str(), timeout, Duration::from_secs(5)).await?;
+
+        let body = json!({
+            "actionToken": token,
+            "itemInstanceId": self.instanceID,
+            "plug": plug,
+            "characterId": owner,
+            "membershipType": self.membershipType,
+        });
+
+        client.post_action(format!("{}/Destiny2/Actions/Items/InsertSocketPlug/", URL_BASE), body.to_string()).await?;
+
+        Ok(())
+    }
+
+    /// Validate a plug against this item's current state, returning the owning character's id
+    async fn validate_plug(&self, client: &ApiClient, plug: &SocketPlug) -> Result<i64> {
+        let owner = self.character_owner.as_ref().ok_or_else(|| anyhow!("Item {} is in the vault and must be on a character to insert plugs", self.instanceID))?;
+        let details = self.get_instance_details(client, owner.membershipId.as_str()).await?;
+
+        let manifest = Manifest::new(client.clone().await);
+        let energy_cost = Item::get_energy_cost(&manifest, plug.plugItemHash).await?;
+        let replaced_energy_cost = match details.sockets.get(plug.socketIndex as usize).and_then(|s| s.plugHash) {
+            Some(hash) => Item::get_energy_cost(&manifest, hash).await?,
+            None => 0,
+        };
+
+        details.validate_plug(plug, energy_cost, replaced_energy_cost)?;
+
+        Ok(owner.characterId.parse::<i64>()?)
+    }
+
+    pub fn is_in_vault(&self) -> bool {
+        self.character_owner.is_none()
+    }
+
+    async fn transfer(&self, client: &ApiClient, body: Value) -> Result<()> {
+        client.post_action(format!("{}/Destiny2/Actions/Items/TransferItem/", URL_BASE), body.to_string()).await?;
+
+        Ok(())
+    }
 }
 
+#[allow(async_fn_in_trait)]
 pub trait Equippable {
-    fn equip(&self) -> Result<()>;
+    async fn equip(&self, client: &ApiClient) -> Result<()>;
+
+    async fn transfer_to_vault(&self, client: &ApiClient) -> Result<()>;
+
+    async fn transfer_to_character(&self, client: &ApiClient, character: &DestinyCharacter) -> Result<()>;
+}
+
+impl Equippable for InventoryItem {
+    /// Equip this item on the character currently holding it
+    async fn equip(&self, client: &ApiClient) -> Result<()> {
+        let owner = self.character_owner.as_ref().ok_or_else(|| anyhow!("Item {} is in the vault and must be transferred to a character before being equipped", self.instanceID))?;
 
-    fn transfer_to_vault(&self) -> Result<()>;
+        let body = json!({
+            "itemId": self.instanceID,
+            "characterId": owner.characterId.parse::<i64>()?,
+            "membershipType": self.membershipType,
+        });
 
-    fn transfer_to_character(&self) -> Result<()>;
-}
\ No newline at end of file
+        client.post_action(format!("{}/Destiny2/Actions/Items/EquipItem/", URL_BASE), body.to_string()).await?;
+
+        Ok(())
+    }
+
+    async fn transfer_to_vault(&self, client: &ApiClient) -> Result<()> {
+        if self.is_in_vault() {
+            return Ok(());
+        }
+
+        self.transfer(client, self.get_json(true)).await
+    }
+
+    /// Move this item to a character, going through the vault if it is held by another character
+    async fn transfer_to_character(&self, client: &ApiClient, character: &DestinyCharacter) -> Result<()> {
+        if let Some(owner) = &self.character_owner {
+            if owner.characterId == character.characterId {
+                return Ok(());
+            }
+
+            if self.isEquipped {
+                return Err(anyhow!("Item {} is equipped on character {} and cannot be transferred", self.instanceID, owner.characterId));
+            }
+
+            self.transfer_to_vault(client).await?;
+        }
+
+        self.transfer(client, self.get_json_for_character(character.characterId.clone(), false)).await
+    }
+}
+
+/// Equip several items at once, all of which must already be on the character
+///
+/// Returns the instance ids that could not be equipped along with their status code
+pub async fn equip_items(client: &ApiClient, character: &DestinyCharacter, item_ids: Vec<i64>) -> Result<Vec<(i64, i32)>> {
+    let body = json!({
+        "itemIds": item_ids,
+        "characterId": character.characterId.parse::<i64>()?,
+        "membershipType": character.membershipType,
+    });
+
+    let resp = client.post_action(format!("{}/Destiny2/Actions/Items/EquipItems/", URL_BASE), body.to_string()).await?;
+
+    let mut failed = vec![];
+
+    if let Some(results) = resp["equipResults"].as_array() {
+        for r in results {
+            let status = r["equipStatus"].as_i64().unwrap_or(0) as i32;
+
+            if status != 1 {
+                failed.push((r["itemInstanceId"].as_str().and_then(|id| id.parse::<i64>().ok()).unwrap_or(0), status));
+            }
+        }
+    }
+
+    Ok(failed)
+}
+
+// https://data.destinysets.com/i/InventoryBucket
+// The buckets that hold equippable gear on a character
+enumize!(InventoryBucket, i64 => {
+    Subclass, 3284755031,
+    KineticWeapons, 1498876634,
+    EnergyWeapons, 2465295065,
+    PowerWeapons, 953998645,
+    Helmet, 3448274439,
+    Gauntlets, 3551918588,
+    ChestArmor, 14239492,
+    LegArmor, 20886954,
+    ClassArmor, 1585787867,
+    Ghost, 4023194814,
+    Vehicle, 2025709351,
+    Ships, 284967655,
+    Emblems, 4274335291
+});
+
+impl InventoryBucket {
+    pub fn is_weapon(&self) -> bool {
+        matches!(self, InventoryBucket::KineticWeapons | InventoryBucket::EnergyWeapons | InventoryBucket::PowerWeapons)
+    }
+
+    pub fn is_armor(&self) -> bool {
+        matches!(self, InventoryBucket::Helmet | InventoryBucket::Gauntlets | InventoryBucket::ChestArmor | InventoryBucket::LegArmor | InventoryBucket::ClassArmor)
+    }
+}
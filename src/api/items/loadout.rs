@@ -0,0 +1,302 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::api::ApiClient::ApiClient;
+use crate::api::manifest::manifest::{Manifest, ManifestEntityType};
+use crate::api::items::instance::SocketPlug;
+use crate::api::items::item::{DestinyItemComponent, equip_items, Equippable, InventoryBucket, InventoryItem};
+use crate::api::user::BungieUser::DestinyComponentType;
+use crate::{BungieUser, DestinyCharacter};
+
+/// A saved set of gear that can be put back on any character of the same class
+///
+/// Loadouts are plain data so they can be stored as JSON and applied later,
+/// even after the items have been moved around between characters and the vault.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Loadout {
+    pub name: String,
+    pub classType: i16,
+    pub subclass: Option<LoadoutItem>,
+    pub weapons: Vec<LoadoutItem>,
+    pub armor: Vec<LoadoutItem>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LoadoutItem {
+    pub itemHash: i64,
+    pub itemInstanceId: i64,
+    pub bucketHash: i64,
+    /// Plugs that were socketed when the loadout was taken, empty unless mods were requested
+    #[serde(default)]
+    pub plugs: Vec<LoadoutPlug>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LoadoutPlug {
+    pub socketIndex: i32,
+    pub plugItemHash: i64,
+}
+
+/// What happened when a loadout was applied to a character
+#[derive(Default)]
+pub struct LoadoutApplyReport {
+    /// Instance ids that are now equipped
+    pub equipped: Vec<i64>,
+    /// Items that could not be found anywhere in the user's inventory
+    pub missing: Vec<LoadoutItem>,
+    /// Items that were found but could not be moved or equipped, with the reason
+    pub failed: Vec<(LoadoutItem, String)>,
+    /// Saved plugs that could not be inserted, with the instance id of their item and the reason
+    pub failed_plugs: Vec<(i64, LoadoutPlug, String)>,
+}
+
+impl Loadout {
+    /// Take a snapshot of what a character currently has equipped
+    ///
+    /// If include_mods is set, the plugs in each item's sockets are saved as well
+    pub async fn snapshot(client: &ApiClient, character: &DestinyCharacter, name: String, include_mods: bool) -> Result<Self> {
+        let mut components = vec![DestinyComponentType::CharacterEquipment];
+
+        if include_mods {
+            components.push(DestinyComponentType::ItemSockets);
+        }
+
+        let resp = character.get_components(client, components).await?;
+        let sockets = resp["itemComponents"]["sockets"]["data"].clone();
+
+        let mut loadout = Self {
+            name,
+            classType: character.classType,
+            subclass: None,
+            weapons: vec![],
+            armor: vec![],
+        };
+
+        for item in serde_json::from_value::<Vec<DestinyItemComponent>>(resp["equipment"]["data"]["items"].clone())? {
+            let item = InventoryItem::new(item, Some(character.clone()), character.membershipType, true);
+
+            let bucket = match InventoryBucket::from(item.bucketHash) {
+                Some(bucket) => bucket,
+                None => continue,
+            };
+
+            let mut loadout_item = LoadoutItem {
+                itemHash: item.item.hash,
+                itemInstanceId: item.instanceID,
+                bucketHash: item.bucketHash,
+                plugs: vec![],
+            };
+
+            if include_mods {
+                loadout_item.plugs = LoadoutPlug::from_sockets(&sockets[item.instanceID.to_string()]["sockets"]);
+            }
+
+            if bucket == InventoryBucket::Subclass {
+                loadout.subclass = Some(loadout_item);
+            } else if bucket.is_weapon() {
+                loadout.weapons.push(loadout_item);
+            } else if bucket.is_armor() {
+                loadout.armor.push(loadout_item);
+            }
+        }
+
+        Ok(loadout)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str::<Loadout>(json)?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Every item in this loadout, subclass first
+    pub fn get_items(&self) -> Vec<LoadoutItem> {
+        let mut vec = vec![];
+
+        if let Some(subclass) = &self.subclass {
+            vec.push(subclass.clone());
+        }
+
+        vec.extend(self.weapons.clone());
+        vec.extend(self.armor.clone());
+
+        vec
+    }
+
+    /// Move every item in this loadout onto the character, equip them and insert their saved plugs
+    ///
+    /// Items are pulled from the vault or other characters as needed. The subclass is
+    /// matched by its hash, as each character has their own copy of it. Anything that
+    /// can't be found, moved or plugged in is listed in the report instead of failing
+    /// the whole loadout. Requires the client to have an access token for this user.
+    pub async fn apply(&self, client: &ApiClient, user: &BungieUser, character: &DestinyCharacter) -> Result<LoadoutApplyReport> {
+        if self.classType != character.classType {
+            return Err(anyhow!("Loadout {} is for class type {} but character {} is class type {}", self.name, self.classType, character.characterId, character.classType));
+        }
+
+        let inventory = InventoryItem::get_all(client, user).await?;
+        let mut report = LoadoutApplyReport::default();
+        let mut ready = vec![];
+
+        for loadout_item in self.get_items() {
+            let found = match InventoryBucket::from(loadout_item.bucketHash) {
+                Some(InventoryBucket::Subclass) => inventory.iter().find(|i| i.item.hash == loadout_item.itemHash
+                    && i.character_owner.as_ref().map(|c| c.characterId == character.characterId).unwrap_or(false)),
+                _ => inventory.iter().find(|i| i.instanceID == loadout_item.itemInstanceId),
+            };
+
+            let item = match found {
+                Some(item) => item,
+                None => {
+                    report.missing.push(loadout_item);
+                    continue;
+                }
+            };
+
+            match item.transfer_to_character(client, character).await {
+                Ok(_) => ready.push((loadout_item, item)),
+                Err(e) => report.failed.push((loadout_item, e.to_string())),
+            }
+        }
+
+        if ready.is_empty() {
+            return Ok(report);
+        }
+
+        let failed = equip_items(client, character, ready.iter().map(|(_, i)| i.instanceID).collect()).await?;
+
+        for (loadout_item, item) in ready {
+            if let Some((_, status)) = failed.iter().find(|(id, _)| *id == item.instanceID) {
+                report.failed.push((loadout_item, format!("Equip failed with status {}", status)));
+                continue;
+            }
+
+            report.equipped.push(item.instanceID);
+
+            if !loadout_item.plugs.is_empty() {
+                Loadout::apply_plugs(client, character, item, &loadout_item.plugs, &mut report).await;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Insert the saved plugs into an item that is now on the character, skipping ones already in place
+    async fn apply_plugs(client: &ApiClient, character: &DestinyCharacter, item: &InventoryItem, plugs: &[LoadoutPlug], report: &mut LoadoutApplyReport) {
+        let item = InventoryItem {
+            item: item.item.clone(),
+            character_owner: Some(character.clone()),
+            membershipType: item.membershipType,
+            instanceID: item.instanceID,
+            bucketHash: item.bucketHash,
+            isEquippable: item.isEquippable,
+            isEquipped: true,
+            stackSize: item.stackSize,
+        };
+
+        let details = match item.get_instance_details(client, character.membershipId.as_str()).await {
+            Ok(details) => details,
+            Err(e) => {
+                for plug in plugs {
+                    report.failed_plugs.push((item.instanceID, plug.clone(), e.to_string()));
+                }
+                return;
+            }
+        };
+
+        for plug in plugs {
+            if details.sockets.get(plug.socketIndex as usize).and_then(|s| s.plugHash) == Some(plug.plugItemHash) {
+                continue;
+            }
+
+            let socket_plug = SocketPlug {
+                socketIndex: plug.socketIndex,
+                socketArrayType: 0,
+                plugItemHash: plug.plugItemHash,
+            };
+
+            if let Err(e) = item.insert_plug_free(client, socket_plug).await {
+                report.failed_plugs.push((item.instanceID, plug.clone(), e.to_string()));
+            }
+        }
+    }
+}
+
+/// One of the in-game loadout slots on a character, from the CharacterLoadouts component
+///
+/// Empty slots have no items and zeroed identifier hashes.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CharacterLoadout {
+    pub colorHash: i64,
+    pub iconHash: i64,
+    pub nameHash: i64,
+    pub items: Vec<CharacterLoadoutItem>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CharacterLoadoutItem {
+    pub itemInstanceId: String,
+    pub plugItemHashes: Vec<i64>,
+}
+
+/// The name, color and icon of an in-game loadout after looking them up in the manifest
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LoadoutIdentifiers {
+    pub name: String,
+    pub colorImagePath: String,
+    pub iconImagePath: String,
+}
+
+impl CharacterLoadout {
+    pub fn is_empty(&self) -> bool {
+        self.items.iter().all(|i| i.itemInstanceId == "0")
+    }
+
+    /// Resolve the color, icon and name hashes of this loadout via the manifest
+    pub async fn get_identifiers(&self, manifest: &Manifest) -> Result<LoadoutIdentifiers> {
+        let name = manifest.manifest_value(ManifestEntityType::LOADOUTNAME, self.nameHash).await?;
+        let color = manifest.manifest_value(ManifestEntityType::LOADOUTCOLOR, self.colorHash).await?;
+        let icon = manifest.manifest_value(ManifestEntityType::LOADOUTICON, self.iconHash).await?;
+
+        Ok(LoadoutIdentifiers {
+            name: name["name"].as_str().unwrap_or_default().to_string(),
+            colorImagePath: color["colorImagePath"].as_str().unwrap_or_default().to_string(),
+            iconImagePath: icon["iconImagePath"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl LoadoutPlug {
+    /// Read the plugs out of an item's "sockets" array from the ItemSockets component
+    fn from_sockets(sockets: &Value) -> Vec<LoadoutPlug> {
+        let mut vec = vec![];
+
+        if let Some(sockets) = sockets.as_array() {
+            for (index, socket) in sockets.iter().enumerate() {
+                if let Some(hash) = socket["plugHash"].as_i64() {
+                    vec.push(LoadoutPlug {
+                        socketIndex: index as i32,
+                        plugItemHash: hash,
+                    });
+                }
+            }
+        }
+
+        vec
+    }
+}
+
+#[test]
+fn loadout_json_round_trip() {
+    let json = r#"{"name":"Raid","classType":1,"subclass":{"itemHash":2453351420,"itemInstanceId":6917529203,"bucketHash":3284755031},
+        "weapons":[{"itemHash":1,"itemInstanceId":2,"bucketHash":1498876634,"plugs":[{"socketIndex":0,"plugItemHash":3}]}],"armor":[]}"#;
+
+    let loadout = Loadout::from_json(json).unwrap();
+    assert_eq!(2, loadout.get_items().len());
+    assert!(loadout.subclass.clone().unwrap().plugs.is_empty());
+
+    let again = Loadout::from_json(loadout.to_json().unwrap().as_str()).unwrap();
+    assert_eq!(3, again.weapons[0].plugs[0].plugItemHash);
+}
@@ -1 +1,3 @@
-pub mod item;
\ No newline at end of file
+pub mod item;
+pub mod loadout;
+pub mod instance;
\ No newline at end of file
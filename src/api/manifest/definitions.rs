@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use crate::api::manifest::manifest::{DisplayProperties, ManifestEntityType};
+
+/// A typed manifest definition
+///
+/// Links each definition struct to the table it lives in so it can be fetched
+/// with get::<T>(hash) from Manifest, LocalManifest or JsonManifest.
+///
+/// Only the commonly used fields are modelled. Every field falls back to its
+/// default if Bungie leaves it out, so new or removed fields never break parsing.
+pub trait Definition: DeserializeOwned {
+    fn entity_type() -> ManifestEntityType;
+}
+
+/// Implement Definition for each struct with the given ManifestEntityType
+macro_rules! definition {
+    ($($def: ident => $typ: ident),+) => {
+        $(
+            impl Definition for $def {
+                fn entity_type() -> ManifestEntityType {
+                    ManifestEntityType::$typ
+                }
+            }
+        )+
+    };
+}
+
+definition!(
+    DestinyInventoryItemDefinition => INVENTORYITEM,
+    DestinyActivityDefinition => ACTIVITY,
+    DestinyActivityModeDefinition => ACTIVITYMODE,
+    DestinyActivityTypeDefinition => ACTIVITYTYPE,
+    DestinyStatDefinition => ITEMSTAT,
+    DestinyClassDefinition => CLASS,
+    DestinyRaceDefinition => RACE,
+    DestinyGenderDefinition => GENDER,
+    DestinyMilestoneDefinition => MILESTONE,
+    DestinyRecordDefinition => RECORD,
+    DestinyPresentationNodeDefinition => PRESENTATIONNODE,
+    DestinyCollectibleDefinition => COLLECTIBLE,
+    DestinyVendorDefinition => VENDOR,
+    DestinyPlugSetDefinition => PLUGSET,
+    DestinySandboxPerkDefinition => SANDBOXPERK,
+    DestinyObjectiveDefinition => OBJECTIVE,
+    DestinySeasonDefinition => SEASON,
+    DestinyDestinationDefinition => DESTINATION,
+    DestinyDamageTypeDefinition => DAMAGETYPE
+);
+
+// https://bungie-net.github.io/multi/schema_Destiny-Definitions-DestinyInventoryItemDefinition.html
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyInventoryItemDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub blacklisted: bool,
+    pub displayProperties: DisplayProperties,
+    pub itemTypeDisplayName: String,
+    pub itemTypeAndTierDisplayName: String,
+    pub flavorText: String,
+    pub screenshot: Option<String>,
+    pub iconWatermark: Option<String>,
+    pub itemType: i32,
+    pub itemSubType: i32,
+    pub classType: i32,
+    pub equippable: bool,
+    pub defaultDamageType: i32,
+    pub defaultDamageTypeHash: Option<i64>,
+    pub damageTypeHashes: Vec<i64>,
+    pub itemCategoryHashes: Vec<i64>,
+    pub collectibleHash: Option<i64>,
+    pub loreHash: Option<i64>,
+    pub inventory: Option<ItemInventoryBlock>,
+    pub equippingBlock: Option<ItemEquippingBlock>,
+    pub stats: Option<ItemStatBlock>,
+    pub sockets: Option<ItemSocketBlock>,
+    pub plug: Option<ItemPlugBlock>,
+    pub perks: Vec<ItemPerkEntry>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemInventoryBlock {
+    pub tierTypeHash: i64,
+    pub tierTypeName: Option<String>,
+    pub tierType: i32,
+    pub bucketTypeHash: i64,
+    pub maxStackSize: i32,
+    pub isInstanceItem: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemEquippingBlock {
+    pub equipmentSlotTypeHash: i64,
+    pub ammoType: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemStatBlock {
+    pub statGroupHash: Option<i64>,
+    /// Keyed by stat hash
+    pub stats: HashMap<String, InventoryItemStat>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct InventoryItemStat {
+    pub statHash: i64,
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemSocketBlock {
+    pub socketEntries: Vec<ItemSocketEntry>,
+    pub socketCategories: Vec<ItemSocketCategory>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemSocketEntry {
+    pub socketTypeHash: i64,
+    pub singleInitialItemHash: i64,
+    pub reusablePlugSetHash: Option<i64>,
+    pub randomizedPlugSetHash: Option<i64>,
+    pub reusablePlugItems: Vec<ItemSocketEntryPlug>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemSocketEntryPlug {
+    pub plugItemHash: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemSocketCategory {
+    pub socketCategoryHash: i64,
+    pub socketIndexes: Vec<i32>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemPlugBlock {
+    pub plugCategoryIdentifier: String,
+    pub plugCategoryHash: i64,
+    pub energyCost: Option<PlugEnergyCost>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PlugEnergyCost {
+    pub energyCost: i32,
+    pub energyTypeHash: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ItemPerkEntry {
+    pub perkHash: i64,
+    pub perkVisibility: i32,
+}
+
+// https://bungie-net.github.io/multi/schema_Destiny-Definitions-DestinyActivityDefinition.html
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyActivityDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub originalDisplayProperties: Option<DisplayProperties>,
+    pub selectionScreenDisplayProperties: Option<DisplayProperties>,
+    pub releaseIcon: Option<String>,
+    pub pgcrImage: Option<String>,
+    pub activityLightLevel: i32,
+    pub tier: i32,
+    pub destinationHash: i64,
+    pub placeHash: i64,
+    pub activityTypeHash: i64,
+    pub isPlaylist: bool,
+    pub isPvP: bool,
+    pub directActivityModeHash: Option<i64>,
+    pub directActivityModeType: Option<i32>,
+    pub activityModeHashes: Vec<i64>,
+    pub activityModeTypes: Vec<i32>,
+    pub modifiers: Vec<ActivityModifierReference>,
+    pub matchmaking: Option<ActivityMatchmakingBlock>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ActivityModifierReference {
+    pub activityModifierHash: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ActivityMatchmakingBlock {
+    pub isMatchmade: bool,
+    pub minParty: i32,
+    pub maxParty: i32,
+    pub maxPlayers: i32,
+    pub requiresGuardianOath: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyActivityModeDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub pgcrImage: Option<String>,
+    pub modeType: i32,
+    pub activityModeCategory: i32,
+    pub isTeamBased: bool,
+    pub isAggregateMode: bool,
+    pub parentHashes: Vec<i64>,
+    pub friendlyName: String,
+    pub display: bool,
+    pub order: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyActivityTypeDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyStatDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub aggregationType: i32,
+    pub hasComputedBlock: bool,
+    pub statCategory: i32,
+    pub interpolate: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyClassDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub classType: i32,
+    /// Keyed by gender hash
+    pub genderedClassNamesByGenderHash: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyRaceDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub raceType: i32,
+    /// Keyed by gender hash
+    pub genderedRaceNamesByGenderHash: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyGenderDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub genderType: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyMilestoneDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub friendlyName: Option<String>,
+    pub milestoneType: i32,
+    pub recruitable: bool,
+    pub showInExplorer: bool,
+    pub showInMilestones: bool,
+    pub isInGameMilestone: bool,
+    pub hasPredictableDates: bool,
+    /// Keyed by reward category hash
+    pub rewards: HashMap<String, MilestoneRewardCategory>,
+}
+
+impl DestinyMilestoneDefinition {
+    pub fn get_reward_category(&self, category_hash: i64) -> Option<&MilestoneRewardCategory> {
+        self.rewards.get(&(category_hash as u32).to_string())
+    }
+
+    /// Find a reward entry in any of the categories
+    pub fn get_reward_entry(&self, reward_entry_hash: i64) -> Option<&MilestoneRewardEntry> {
+        self.rewards.values().find_map(|category| category.get_entry(reward_entry_hash))
+    }
+
+    /// The reward categories in the order they are displayed in game
+    pub fn get_reward_categories(&self) -> Vec<&MilestoneRewardCategory> {
+        let mut vec = self.rewards.values().collect::<Vec<&MilestoneRewardCategory>>();
+        vec.sort_by_key(|c| (c.order, c.categoryHash));
+
+        vec
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct MilestoneRewardCategory {
+    pub categoryHash: i64,
+    pub categoryIdentifier: String,
+    pub displayProperties: DisplayProperties,
+    /// Keyed by reward entry hash
+    pub rewardEntries: HashMap<String, MilestoneRewardEntry>,
+    pub order: i32,
+}
+
+impl MilestoneRewardCategory {
+    pub fn get_entry(&self, reward_entry_hash: i64) -> Option<&MilestoneRewardEntry> {
+        self.rewardEntries.get(&(reward_entry_hash as u32).to_string())
+    }
+
+    /// The reward entries in the order they are displayed in game
+    pub fn get_entries(&self) -> Vec<&MilestoneRewardEntry> {
+        let mut vec = self.rewardEntries.values().collect::<Vec<&MilestoneRewardEntry>>();
+        vec.sort_by_key(|e| (e.order, e.rewardEntryHash));
+
+        vec
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct MilestoneRewardEntry {
+    pub rewardEntryHash: i64,
+    pub rewardEntryIdentifier: String,
+    pub items: Vec<MilestoneRewardItem>,
+    pub vendorHash: Option<i64>,
+    pub displayProperties: DisplayProperties,
+    pub order: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct MilestoneRewardItem {
+    pub itemHash: i64,
+    pub quantity: i32,
+    pub hasConditionalVisibility: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyRecordDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub scope: i32,
+    pub loreHash: Option<i64>,
+    pub objectiveHashes: Vec<i64>,
+    pub recordValueStyle: i32,
+    pub forTitleGating: bool,
+    pub titleInfo: Option<RecordTitleBlock>,
+    pub parentNodeHashes: Vec<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RecordTitleBlock {
+    pub hasTitle: bool,
+    /// Keyed by gender name, e.g. "Male"
+    pub titlesByGender: HashMap<String, String>,
+    /// Keyed by gender hash
+    pub titlesByGenderHash: HashMap<String, String>,
+    pub gildingTrackingRecordHash: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyPresentationNodeDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub originalIcon: Option<String>,
+    pub rootViewIcon: Option<String>,
+    pub nodeType: i32,
+    pub scope: i32,
+    pub objectiveHash: Option<i64>,
+    pub completionRecordHash: Option<i64>,
+    pub children: PresentationNodeChildren,
+    pub displayStyle: i32,
+    pub screenStyle: i32,
+    pub parentNodeHashes: Vec<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PresentationNodeChildren {
+    pub presentationNodes: Vec<PresentationNodeChildEntry>,
+    pub collectibles: Vec<PresentationNodeChildEntry>,
+    pub records: Vec<PresentationNodeChildEntry>,
+    pub metrics: Vec<PresentationNodeChildEntry>,
+}
+
+/// Only the hash matching the kind of child it is will be set
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PresentationNodeChildEntry {
+    pub presentationNodeHash: Option<i64>,
+    pub collectibleHash: Option<i64>,
+    pub recordHash: Option<i64>,
+    pub metricHash: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyCollectibleDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub scope: i32,
+    pub sourceString: String,
+    pub sourceHash: Option<i64>,
+    pub itemHash: i64,
+    pub presentationNodeType: i32,
+    pub parentNodeHashes: Vec<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyVendorDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub vendorIdentifier: Option<String>,
+    pub vendorPortrait: Option<String>,
+    pub vendorBanner: Option<String>,
+    pub buyString: Option<String>,
+    pub sellString: Option<String>,
+    pub displayItemHash: i64,
+    pub inhibitBuying: bool,
+    pub inhibitSelling: bool,
+    pub factionHash: i64,
+    pub resetIntervalMinutes: i32,
+    pub resetOffsetMinutes: i32,
+    pub enabled: bool,
+    pub visible: bool,
+    pub itemList: Vec<VendorItem>,
+    pub groups: Vec<VendorGroupReference>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct VendorItem {
+    pub vendorItemIndex: i32,
+    pub itemHash: i64,
+    pub quantity: i32,
+    pub displayCategoryIndex: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct VendorGroupReference {
+    pub vendorGroupHash: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyPlugSetDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub reusablePlugItems: Vec<PlugSetEntry>,
+    pub isFakePlugSet: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct PlugSetEntry {
+    pub plugItemHash: i64,
+    pub currentlyCanRoll: bool,
+    pub weight: f32,
+    pub alternateWeight: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinySandboxPerkDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub perkIdentifier: Option<String>,
+    pub isDisplayable: bool,
+    pub damageType: i32,
+    pub damageTypeHash: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyObjectiveDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub progressDescription: String,
+    pub completionValue: i32,
+    pub scope: i32,
+    pub locationHash: i64,
+    pub allowNegativeValue: bool,
+    pub allowValueChangeWhenCompleted: bool,
+    pub isCountingDownward: bool,
+    pub allowOvercompletion: bool,
+    pub showValueOnComplete: bool,
+    pub minimumVisibilityThreshold: i32,
+    pub valueStyle: i32,
+    pub completedValueStyle: i32,
+    pub inProgressValueStyle: i32,
+    pub uiLabel: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinySeasonDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub backgroundImagePath: Option<String>,
+    pub seasonNumber: i32,
+    pub startDate: Option<String>,
+    pub endDate: Option<String>,
+    pub seasonPassHash: Option<i64>,
+    pub seasonPassProgressionHash: Option<i64>,
+    pub artifactItemHash: Option<i64>,
+    pub sealPresentationNodeHash: Option<i64>,
+    pub seasonalChallengesPresentationNodeHash: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyDestinationDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub placeHash: i64,
+    pub defaultFreeroamActivityHash: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinyDamageTypeDefinition {
+    pub hash: i64,
+    pub index: i32,
+    pub redacted: bool,
+    pub displayProperties: DisplayProperties,
+    pub transparentIconPath: Option<String>,
+    pub showIcon: bool,
+    pub enumValue: i32,
+    pub color: Option<DamageTypeColor>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DamageTypeColor {
+    pub red: i16,
+    pub green: i16,
+    pub blue: i16,
+    pub alpha: i16,
+}
+
+#[test]
+fn definition_tolerates_missing_and_unknown_fields() {
+    let def = serde_json::from_str::<DestinyInventoryItemDefinition>(r#"{
+        "hash": 347366834, "displayProperties": {"name": "Ace of Spades", "hasIcon": true, "icon": "/common/destiny2_content/icons/ace.jpg"},
+        "itemTypeDisplayName": "Hand Cannon", "inventory": {"tierTypeName": "Exotic", "tierType": 6, "bucketTypeHash": 1498876634},
+        "sockets": {"socketEntries": [{"socketTypeHash": 1, "singleInitialItemHash": 2, "reusablePlugSetHash": 3}]},
+        "someFieldBungieAddedLater": {"nested": [1, 2, 3]}
+    }"#).unwrap();
+
+    assert_eq!("Ace of Spades", def.displayProperties.name);
+    assert_eq!("", def.displayProperties.description);
+    assert_eq!(Some(String::from("Exotic")), def.inventory.unwrap().tierTypeName);
+    assert_eq!(Some(3), def.sockets.unwrap().socketEntries[0].reusablePlugSetHash);
+    assert!(def.perks.is_empty());
+    assert!(DestinyInventoryItemDefinition::entity_type() == ManifestEntityType::INVENTORYITEM);
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestSource};
+#[cfg(test)]
+use crate::api::manifest::manifest::TestSource;
+
+/// Top level fields that change between versions without the definition itself changing
+const IGNORED_FIELDS: [&str; 1] = ["index"];
+
+/// What changed between two versions of the manifest
+///
+/// Compare any two manifest sources with compare(), or the installed and previous
+/// versions of a LocalManifest with LocalManifest::diff_previous(). The report can
+/// be saved as JSON or rendered as Markdown for patch notes.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct ManifestDiff {
+    pub oldVersion: String,
+    pub newVersion: String,
+    /// Only tables with at least one change are included
+    pub tables: Vec<TableDiff>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct TableDiff {
+    /// The definition name, e.g. DestinyInventoryItemDefinition
+    pub entityType: String,
+    pub added: Vec<DefinitionSummary>,
+    pub removed: Vec<DefinitionSummary>,
+    pub modified: Vec<ModifiedDefinition>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DefinitionSummary {
+    pub hash: i64,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ModifiedDefinition {
+    pub hash: i64,
+    pub name: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A single changed value, the path is dotted with array indexes in brackets, e.g. sockets.socketEntries[3].singleInitialItemHash
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct FieldChange {
+    pub path: String,
+    /// None if the field was added
+    pub old: Option<Value>,
+    /// None if the field was removed
+    pub new: Option<Value>,
+}
+
+impl ManifestDiff {
+    /// Compare the given definition types between two manifests
+    ///
+    /// A type that only exists in one of the manifests counts as entirely added or removed,
+    /// any other error reading a table is returned. The versions are left empty, set them
+    /// if they should appear in the report.
+    pub fn compare<A: ManifestSource, B: ManifestSource>(old: &A, new: &B, types: Vec<ManifestEntityType>) -> Result<Self> {
+        let mut diff = ManifestDiff::default();
+
+        for typ in types {
+            let (has_old, has_new) = (old.has_type(typ)?, new.has_type(typ)?);
+
+            if !has_old && !has_new {
+                return Err(anyhow!("Neither manifest has {}", typ.get()));
+            }
+
+            let old_table = if has_old { old.get_all_raw(typ)? } else { vec![] };
+            let new_table = if has_new { new.get_all_raw(typ)? } else { vec![] };
+
+            let table = TableDiff::compare(typ.get(), old_table, new_table);
+
+            if !table.is_empty() {
+                diff.tables.push(table);
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Compare every definition type, skipping types that neither manifest has
+    pub fn compare_all<A: ManifestSource, B: ManifestSource>(old: &A, new: &B) -> Result<Self> {
+        let mut types = vec![];
+
+        for typ in ManifestEntityType::get_all() {
+            if old.has_type(typ)? || new.has_type(typ)? {
+                types.push(typ);
+            }
+        }
+
+        ManifestDiff::compare(old, new, types)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn get_table(&self, typ: ManifestEntityType) -> Option<&TableDiff> {
+        self.tables.iter().find(|t| t.entityType == typ.get())
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str::<ManifestDiff>(json)?)
+    }
+
+    /// Render the report as Markdown, with one section per changed table
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+
+        let _ = writeln!(md, "# Manifest changes");
+        let _ = writeln!(md);
+
+        if !self.oldVersion.is_empty() || !self.newVersion.is_empty() {
+            let _ = writeln!(md, "`{}` → `{}`", self.oldVersion, self.newVersion);
+            let _ = writeln!(md);
+        }
+
+        if self.tables.is_empty() {
+            let _ = writeln!(md, "No changes.");
+            return md;
+        }
+
+        for table in &self.tables {
+            let _ = writeln!(md, "## {}", table.entityType);
+            let _ = writeln!(md);
+            let _ = writeln!(md, "{} added, {} removed, {} modified", table.added.len(), table.removed.len(), table.modified.len());
+            let _ = writeln!(md);
+
+            if !table.added.is_empty() {
+                let _ = writeln!(md, "### Added");
+                let _ = writeln!(md);
+
+                for def in &table.added {
+                    let _ = writeln!(md, "- {} (`{}`)", markdown_name(&def.name), def.hash);
+                }
+
+                let _ = writeln!(md);
+            }
+
+            if !table.removed.is_empty() {
+                let _ = writeln!(md, "### Removed");
+                let _ = writeln!(md);
+
+                for def in &table.removed {
+                    let _ = writeln!(md, "- {} (`{}`)", markdown_name(&def.name), def.hash);
+                }
+
+                let _ = writeln!(md);
+            }
+
+            if !table.modified.is_empty() {
+                let _ = writeln!(md, "### Modified");
+                let _ = writeln!(md);
+
+                for def in &table.modified {
+                    let _ = writeln!(md, "- {} (`{}`)", markdown_name(&def.name), def.hash);
+
+                    for change in &def.changes {
+                        let _ = writeln!(md, "  - `{}`: {} → {}", change.path, markdown_value(&change.old), markdown_value(&change.new));
+                    }
+                }
+
+                let _ = writeln!(md);
+            }
+        }
+
+        md
+    }
+}
+
+impl TableDiff {
+    /// Compare two full tables of definitions, matched by hash
+    pub fn compare(entity_type: String, old: Vec<Value>, new: Vec<Value>) -> Self {
+        let old = by_hash(old);
+        let new = by_hash(new);
+
+        let mut table = TableDiff {
+            entityType: entity_type,
+            ..Default::default()
+        };
+
+        for (hash, def) in &new {
+            match old.get(hash) {
+                None => table.added.push(DefinitionSummary { hash: *hash, name: display_name(def) }),
+                Some(old_def) => {
+                    let changes = diff_values(old_def, def);
+
+                    if !changes.is_empty() {
+                        table.modified.push(ModifiedDefinition { hash: *hash, name: display_name(def), changes });
+                    }
+                }
+            }
+        }
+
+        for (hash, def) in &old {
+            if !new.contains_key(hash) {
+                table.removed.push(DefinitionSummary { hash: *hash, name: display_name(def) });
+            }
+        }
+
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// The field-level differences between two definitions, ignoring IGNORED_FIELDS
+pub fn diff_values(old: &Value, new: &Value) -> Vec<FieldChange> {
+    let mut changes = vec![];
+
+    match (old, new) {
+        (Value::Object(o), Value::Object(n)) => {
+            let keys = o.keys().chain(n.keys())
+                .filter(|k| !IGNORED_FIELDS.contains(&k.as_str()))
+                .collect::<BTreeSet<&String>>();
+
+            for key in keys {
+                diff_into(key.clone(), o.get(key), n.get(key), &mut changes);
+            }
+        }
+        _ => diff_into(String::new(), Some(old), Some(new), &mut changes),
+    }
+
+    changes
+}
+
+fn diff_into(path: String, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<FieldChange>) {
+    match (old, new) {
+        (Some(Value::Object(o)), Some(Value::Object(n))) => {
+            let keys = o.keys().chain(n.keys()).collect::<BTreeSet<&String>>();
+
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff_into(child, o.get(key), n.get(key), changes);
+            }
+        }
+        (Some(Value::Array(o)), Some(Value::Array(n))) => {
+            // Elements present in both are lined up first, so an insertion is one change rather than a shift of everything after it
+            for (i, j) in align(o, n) {
+                let index = j.or(i).unwrap_or_default();
+                diff_into(format!("{}[{}]", path, index), i.map(|i| &o[i]), j.map(|j| &n[j]), changes);
+            }
+        }
+        (o, n) => {
+            if o != n {
+                changes.push(FieldChange {
+                    path,
+                    old: o.cloned(),
+                    new: n.cloned(),
+                });
+            }
+        }
+    }
+}
+
+/// Arrays longer than this are compared by index, as lining them up is quadratic
+const MAX_ALIGNED_LENGTH: usize = 1000;
+
+/// Pair up the indexes of two arrays, None on one side for an element that was added or removed
+///
+/// Equal elements are matched by their longest common subsequence. The elements left over
+/// between two matches are paired by position, so a changed element is still diffed field by field.
+fn align(old: &[Value], new: &[Value]) -> Vec<(Option<usize>, Option<usize>)> {
+    if old == new || old.len() > MAX_ALIGNED_LENGTH || new.len() > MAX_ALIGNED_LENGTH {
+        return (0..old.len().max(new.len()))
+            .map(|i| ((i < old.len()).then_some(i), (i < new.len()).then_some(i)))
+            .collect();
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut added) = (vec![], vec![]);
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            pair_gap(&mut removed, &mut added, &mut pairs);
+            pairs.push((Some(i), Some(j)));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            added.push(j);
+            j += 1;
+        } else {
+            removed.push(i);
+            i += 1;
+        }
+    }
+
+    pair_gap(&mut removed, &mut added, &mut pairs);
+
+    pairs
+}
+
+/// Pair the removed and added elements between two matches by position
+fn pair_gap(removed: &mut Vec<usize>, added: &mut Vec<usize>, pairs: &mut Vec<(Option<usize>, Option<usize>)>) {
+    for k in 0..removed.len().max(added.len()) {
+        pairs.push((removed.get(k).copied(), added.get(k).copied()));
+    }
+
+    removed.clear();
+    added.clear();
+}
+
+fn by_hash(table: Vec<Value>) -> BTreeMap<i64, Value> {
+    table.into_iter()
+        .filter_map(|def| def["hash"].as_i64().map(|hash| (hash, def)))
+        .collect()
+}
+
+fn display_name(def: &Value) -> String {
+    def["displayProperties"]["name"].as_str().unwrap_or_default().to_string()
+}
+
+fn markdown_name(name: &str) -> String {
+    if name.is_empty() {
+        String::from("*Unnamed*")
+    } else {
+        format!("**{}**", name)
+    }
+}
+
+fn markdown_value(val: &Option<Value>) -> String {
+    match val {
+        None => String::from("*none*"),
+        Some(val) => format!("`{}`", val),
+    }
+}
+
+#[test]
+fn manifest_diff_tables() {
+    let old = TestSource::new(vec![
+        (ManifestEntityType::INVENTORYITEM, serde_json::from_str(r#"[
+            {"hash": 1, "index": 10, "displayProperties": {"name": "Sunshot"}, "perks": [111, 222]},
+            {"hash": 2, "index": 11, "displayProperties": {"name": "Old Gun"}}
+        ]"#).unwrap()),
+        (ManifestEntityType::RECORD, serde_json::from_str(r#"[{"hash": 5, "displayProperties": {"name": "Gone"}}]"#).unwrap()),
+    ]);
+
+    let new = TestSource::new(vec![
+        (ManifestEntityType::INVENTORYITEM, serde_json::from_str(r#"[
+            {"hash": 1, "index": 12, "displayProperties": {"name": "Sunshot", "icon": "/a.png"}, "perks": [111, 333, 444]},
+            {"hash": 3, "index": 13, "displayProperties": {"name": "New Gun"}}
+        ]"#).unwrap()),
+    ]);
+
+    let diff = ManifestDiff::compare(&old, &new, vec![ManifestEntityType::INVENTORYITEM, ManifestEntityType::RECORD]).unwrap();
+    let items = diff.get_table(ManifestEntityType::INVENTORYITEM).unwrap();
+
+    assert_eq!(vec![3], items.added.iter().map(|d| d.hash).collect::<Vec<i64>>());
+    assert_eq!(vec![2], items.removed.iter().map(|d| d.hash).collect::<Vec<i64>>());
+    assert_eq!(1, items.modified.len());
+
+    // The index shifts between versions and isn't reported
+    assert_eq!(vec![
+        FieldChange { path: String::from("displayProperties.icon"), old: None, new: Some(Value::from("/a.png")) },
+        FieldChange { path: String::from("perks[1]"), old: Some(Value::from(222)), new: Some(Value::from(333)) },
+        FieldChange { path: String::from("perks[2]"), old: None, new: Some(Value::from(444)) },
+    ], items.modified[0].changes);
+
+    // A table missing from the new version is entirely removed
+    assert_eq!(1, diff.get_table(ManifestEntityType::RECORD).unwrap().removed.len());
+
+    // Inserting into an array is reported once instead of shifting every element after it
+    assert_eq!(vec![FieldChange { path: String::from("perks[1]"), old: None, new: Some(Value::from(9)) }],
+               diff_values(&serde_json::json!({"perks": [1, 2, 3]}), &serde_json::json!({"perks": [1, 9, 2, 3]})));
+    assert_eq!(vec![FieldChange { path: String::from("perks[0]"), old: Some(Value::from(1)), new: None }],
+               diff_values(&serde_json::json!({"perks": [1, 2, 3]}), &serde_json::json!({"perks": [2, 3]})));
+
+    // Only a missing table counts as removed, other errors are returned
+    assert!(ManifestDiff::compare(&old, &TestSource::broken(), vec![ManifestEntityType::INVENTORYITEM]).is_err());
+    assert_eq!(2, ManifestDiff::compare_all(&old, &new).unwrap().tables.len());
+
+    let md = diff.to_markdown();
+    assert!(md.contains("## DestinyInventoryItemDefinition"));
+    assert!(md.contains("- **New Gun** (`3`)"));
+    assert!(md.contains("  - `perks[1]`: `222` → `333`"));
+
+    assert_eq!(2, ManifestDiff::from_json(diff.to_json().unwrap().as_str()).unwrap().tables.len());
+}
@@ -0,0 +1,360 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestSource};
+#[cfg(test)]
+use crate::api::manifest::manifest::TestSource;
+
+/// Columns every export has, which the exporter's columns can't be named
+const RESERVED_COLUMNS: [&str; 2] = ["hash", "json"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One JSON definition per line
+    JsonLines,
+    /// One row per definition with the exporter's columns
+    Csv,
+    /// A database with a table per definition type and a real column for each of the exporter's columns
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name.to_lowercase().as_str() {
+            "jsonl" | "jsonlines" | "ndjson" => Some(ExportFormat::JsonLines),
+            "csv" => Some(ExportFormat::Csv),
+            #[cfg(feature = "sqlite")]
+            "sqlite" | "sqlite3" | "db" => Some(ExportFormat::Sqlite),
+            _ => None,
+        }
+    }
+
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv",
+            #[cfg(feature = "sqlite")]
+            ExportFormat::Sqlite => "sqlite3",
+        }
+    }
+}
+
+/// A flattened column, read from the definition at a dotted path such as inventory.tierTypeName
+#[derive(Clone)]
+pub struct ExportColumn {
+    pub name: String,
+    pub path: String,
+}
+
+impl ExportColumn {
+    pub fn new(name: &str, path: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    /// Parse name=path, or just a path which is then also used as the name
+    pub fn parse(column: &str) -> Self {
+        match column.split_once('=') {
+            Some((name, path)) => ExportColumn::new(name.trim(), path.trim()),
+            None => ExportColumn::new(column.trim(), column.trim()),
+        }
+    }
+
+    /// The columns that are useful for a definition type, the hash is always exported on its own
+    pub fn defaults(typ: ManifestEntityType) -> Vec<ExportColumn> {
+        let mut vec = vec![
+            ExportColumn::new("name", "displayProperties.name"),
+            ExportColumn::new("description", "displayProperties.description"),
+            ExportColumn::new("icon", "displayProperties.icon"),
+        ];
+
+        match typ {
+            ManifestEntityType::INVENTORYITEM => vec.extend(vec![
+                ExportColumn::new("itemType", "itemType"),
+                ExportColumn::new("itemSubType", "itemSubType"),
+                ExportColumn::new("itemTypeDisplayName", "itemTypeDisplayName"),
+                ExportColumn::new("tierType", "inventory.tierType"),
+                ExportColumn::new("tierTypeName", "inventory.tierTypeName"),
+                ExportColumn::new("classType", "classType"),
+                ExportColumn::new("bucketTypeHash", "inventory.bucketTypeHash"),
+                ExportColumn::new("defaultDamageType", "defaultDamageType"),
+            ]),
+            ManifestEntityType::ACTIVITY => vec.extend(vec![
+                ExportColumn::new("activityTypeHash", "activityTypeHash"),
+                ExportColumn::new("destinationHash", "destinationHash"),
+                ExportColumn::new("directActivityModeType", "directActivityModeType"),
+                ExportColumn::new("tier", "tier"),
+                ExportColumn::new("activityLightLevel", "activityLightLevel"),
+                ExportColumn::new("isPvP", "isPvP"),
+            ]),
+            ManifestEntityType::RECORD => vec.extend(vec![
+                ExportColumn::new("scope", "scope"),
+                ExportColumn::new("presentationNodeType", "presentationNodeType"),
+                ExportColumn::new("loreHash", "loreHash"),
+            ]),
+            _ => {}
+        }
+
+        vec.push(ExportColumn::new("redacted", "redacted"));
+
+        vec
+    }
+
+    /// Read the column from a definition, Null if the path doesn't exist
+    pub fn read(&self, def: &Value) -> Value {
+        let pointer = format!("/{}", self.path.replace('.', "/"));
+
+        def.pointer(pointer.as_str()).cloned().unwrap_or(Value::Null)
+    }
+}
+
+/// Dumps manifest tables for use in other tools
+///
+/// Works over any manifest source, usually a LocalManifest. Unless columns are set
+/// explicitly, ExportColumn::defaults() is used for each definition type.
+pub struct ManifestExporter<'a, M: ManifestSource> {
+    manifest: &'a M,
+    columns: Option<Vec<ExportColumn>>,
+}
+
+impl<'a, M: ManifestSource> ManifestExporter<'a, M> {
+    pub fn new(manifest: &'a M) -> Self {
+        Self {
+            manifest,
+            columns: None,
+        }
+    }
+
+    /// Export these columns for every type instead of the defaults
+    pub fn with_columns(mut self, columns: Vec<ExportColumn>) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    pub fn get_columns(&self, typ: ManifestEntityType) -> Vec<ExportColumn> {
+        self.columns.clone().unwrap_or_else(|| ExportColumn::defaults(typ))
+    }
+
+    /// The columns for a type, failing if one would collide with the hash or json columns or another column
+    fn get_checked_columns(&self, typ: ManifestEntityType) -> Result<Vec<ExportColumn>> {
+        let columns = self.get_columns(typ);
+        let mut names = RESERVED_COLUMNS.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+
+        for column in &columns {
+            // SQLite compares column names without case
+            let name = column.name.to_lowercase();
+
+            if RESERVED_COLUMNS.contains(&name.as_str()) {
+                return Err(anyhow!("The column name {} is reserved, give the column another name with name=path", column.name));
+            }
+
+            if names.contains(&name) {
+                return Err(anyhow!("The column name {} is used more than once", column.name));
+            }
+
+            names.push(name);
+        }
+
+        Ok(columns)
+    }
+
+    /// Write every definition of a type as a line of JSON, returning how many were written
+    pub fn export_jsonl<W: Write>(&self, typ: ManifestEntityType, writer: &mut W) -> Result<usize> {
+        let defs = self.manifest.get_all_raw(typ)?;
+
+        for def in &defs {
+            writeln!(writer, "{}", def)?;
+        }
+
+        Ok(defs.len())
+    }
+
+    /// Write every definition of a type as a CSV row with a header, returning how many rows were written
+    pub fn export_csv<W: Write>(&self, typ: ManifestEntityType, writer: &mut W) -> Result<usize> {
+        let columns = self.get_checked_columns(typ)?;
+        let defs = self.manifest.get_all_raw(typ)?;
+
+        let header = std::iter::once(String::from("hash")).chain(columns.iter().map(|c| c.name.clone())).map(|h| csv_field(&h)).collect::<Vec<String>>();
+        writeln!(writer, "{}", header.join(","))?;
+
+        for def in &defs {
+            let row = std::iter::once(def["hash"].clone()).chain(columns.iter().map(|c| c.read(def))).map(|v| csv_field(&csv_value(&v))).collect::<Vec<String>>();
+            writeln!(writer, "{}", row.join(","))?;
+        }
+
+        Ok(defs.len())
+    }
+
+    /// Write the definition types into a database with a table for each
+    ///
+    /// Each table has the hash as its primary key, a column for each of the exporter's
+    /// columns and the original definition in a json column. Existing tables are replaced.
+    #[cfg(feature = "sqlite")]
+    pub fn export_sqlite(&self, types: Vec<ManifestEntityType>, path: &Path) -> Result<usize> {
+        let connection = sqlite::Connection::open(path)?;
+        let mut total = 0;
+
+        for typ in types {
+            let columns = self.get_checked_columns(typ)?;
+            let defs = self.manifest.get_all_raw(typ)?;
+
+            let definitions = columns.iter()
+                .map(|c| format!("{} {}", sql_identifier(&c.name), sql_type(defs.iter().map(|d| c.read(d)))))
+                .collect::<Vec<String>>();
+
+            connection.execute(format!("DROP TABLE IF EXISTS {}", sql_identifier(&typ.get())))?;
+            connection.execute(format!("CREATE TABLE {} (hash INTEGER PRIMARY KEY NOT NULL{}{}, json TEXT NOT NULL)", sql_identifier(&typ.get()), if definitions.is_empty() { "" } else { ", " }, definitions.join(", ")))?;
+
+            let placeholders = vec!["?"; columns.len() + 2].join(", ");
+            let mut statement = connection.prepare(format!("INSERT INTO {} VALUES ({})", sql_identifier(&typ.get()), placeholders))?;
+
+            connection.execute("BEGIN")?;
+
+            for def in &defs {
+                statement.reset()?;
+                statement.bind(1, def["hash"].as_i64().unwrap_or(0))?;
+
+                for (i, column) in columns.iter().enumerate() {
+                    match column.read(def) {
+                        Value::Null => statement.bind(i + 2, ())?,
+                        Value::Bool(b) => statement.bind(i + 2, b as i64)?,
+                        Value::Number(n) if n.is_i64() || n.is_u64() => statement.bind(i + 2, n.as_i64().unwrap_or(0))?,
+                        Value::Number(n) => statement.bind(i + 2, n.as_f64().unwrap_or(0.0))?,
+                        Value::String(s) => statement.bind(i + 2, s.as_str())?,
+                        other => statement.bind(i + 2, other.to_string().as_str())?,
+                    }
+                }
+
+                statement.bind(columns.len() + 2, def.to_string().as_str())?;
+
+                while statement.next()? != sqlite::State::Done {}
+            }
+
+            connection.execute("COMMIT")?;
+            total += defs.len();
+        }
+
+        Ok(total)
+    }
+
+    /// Export the types into a directory, a file per type or a single manifest.sqlite3
+    ///
+    /// Returns the files that were written
+    pub fn export_to_dir(&self, types: Vec<ManifestEntityType>, format: ExportFormat, dir: &Path) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(dir)?;
+
+        #[cfg(feature = "sqlite")]
+        if format == ExportFormat::Sqlite {
+            let file = dir.join(format!("manifest.{}", format.get_extension()));
+            self.export_sqlite(types, &file)?;
+
+            return Ok(vec![file]);
+        }
+
+        let mut files = vec![];
+
+        for typ in types {
+            let file = dir.join(format!("{}.{}", typ.get(), format.get_extension()));
+            let mut writer = BufWriter::new(File::create(&file)?);
+
+            match format {
+                ExportFormat::JsonLines => self.export_jsonl(typ, &mut writer)?,
+                ExportFormat::Csv => self.export_csv(typ, &mut writer)?,
+                #[cfg(feature = "sqlite")]
+                ExportFormat::Sqlite => return Err(anyhow!("SQLite exports are written to a single file")),
+            };
+
+            writer.flush()?;
+            files.push(file);
+        }
+
+        Ok(files)
+    }
+}
+
+/// Find a definition type by its table name, e.g. DestinyInventoryItemDefinition
+pub fn entity_type_from_name(name: &str) -> Result<ManifestEntityType> {
+    ManifestEntityType::from(name.to_string()).ok_or_else(|| anyhow!("Unknown definition type {}", name))
+}
+
+fn csv_value(val: &Value) -> String {
+    match val {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Quote a field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sql_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The narrowest column type that fits every value
+#[cfg(feature = "sqlite")]
+fn sql_type(values: impl Iterator<Item = Value>) -> &'static str {
+    let mut typ = "INTEGER";
+
+    for val in values {
+        match val {
+            Value::Null | Value::Bool(_) => {}
+            Value::Number(n) if n.is_i64() || n.is_u64() => {}
+            Value::Number(_) => typ = "REAL",
+            _ => return "TEXT",
+        }
+    }
+
+    typ
+}
+
+#[test]
+fn export_csv_and_sqlite() {
+    let source = TestSource::new(vec![(ManifestEntityType::INVENTORYITEM, serde_json::from_str(r#"[
+        {"hash": 347366834, "displayProperties": {"name": "Ace of Spades", "description": "\"Ace\", the card"}, "itemType": 3, "inventory": {"tierType": 6}},
+        {"hash": 3, "displayProperties": {"name": "Redacted"}, "redacted": true}
+    ]"#).unwrap())]);
+
+    let exporter = ManifestExporter::new(&source).with_columns(vec![ExportColumn::parse("name=displayProperties.name"), ExportColumn::parse("description=displayProperties.description"), ExportColumn::parse("inventory.tierType")]);
+
+    let mut csv = vec![];
+    assert_eq!(2, exporter.export_csv(ManifestEntityType::INVENTORYITEM, &mut csv).unwrap());
+    assert_eq!("hash,name,description,inventory.tierType\n347366834,Ace of Spades,\"\"\"Ace\"\", the card\",6\n3,Redacted,,\n", String::from_utf8(csv).unwrap());
+
+    // Columns can't collide with the hash or json columns, or with each other
+    assert!(ManifestExporter::new(&source).with_columns(vec![ExportColumn::parse("hash")]).export_csv(ManifestEntityType::INVENTORYITEM, &mut vec![]).is_err());
+    assert!(ManifestExporter::new(&source).with_columns(vec![ExportColumn::parse("JSON=displayProperties")]).export_csv(ManifestEntityType::INVENTORYITEM, &mut vec![]).is_err());
+    assert!(ManifestExporter::new(&source).with_columns(vec![ExportColumn::parse("name=displayProperties.name"), ExportColumn::parse("Name=itemType")]).export_csv(ManifestEntityType::INVENTORYITEM, &mut vec![]).is_err());
+
+    #[cfg(feature = "sqlite")]
+    {
+        let file = std::env::temp_dir().join("rustiny_export_test.sqlite3");
+        let _ = fs::remove_file(&file);
+
+        exporter.export_sqlite(vec![ManifestEntityType::INVENTORYITEM], &file).unwrap();
+
+        let connection = sqlite::Connection::open(&file).unwrap();
+        let mut statement = connection.prepare("SELECT name, \"inventory.tierType\" FROM DestinyInventoryItemDefinition WHERE hash = 347366834").unwrap();
+        assert_eq!(sqlite::State::Row, statement.next().unwrap());
+        assert_eq!("Ace of Spades", statement.read::<String>(0).unwrap());
+        assert_eq!(6, statement.read::<i64>(1).unwrap());
+
+        assert!(ManifestExporter::new(&source).with_columns(vec![ExportColumn::parse("hash=displayProperties.name")]).export_sqlite(vec![ManifestEntityType::INVENTORYITEM], &file).is_err());
+
+        let _ = fs::remove_file(file);
+    }
+}
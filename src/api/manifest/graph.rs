@@ -0,0 +1,265 @@
+use std::collections::{HashSet, VecDeque};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestSource};
+#[cfg(test)]
+use crate::api::manifest::manifest::TestSource;
+
+/// A field of one definition type that holds the hash of another definition
+///
+/// The path is dotted, "[]" visits every element of an array and "*" every value
+/// of a map, e.g. sockets.socketEntries[].reusablePlugSetHash or stats.stats.*.statHash
+#[derive(Clone)]
+pub struct ReferenceRule {
+    pub from: ManifestEntityType,
+    pub path: String,
+    pub to: ManifestEntityType,
+}
+
+impl ReferenceRule {
+    pub fn new(from: ManifestEntityType, path: &str, to: ManifestEntityType) -> Self {
+        Self {
+            from,
+            path: path.to_string(),
+            to,
+        }
+    }
+
+    /// The reference fields of the commonly used definitions
+    pub fn defaults() -> Vec<ReferenceRule> {
+        use ManifestEntityType::*;
+
+        let rules = [
+            (INVENTORYITEM, "sockets.socketEntries[].singleInitialItemHash", INVENTORYITEM),
+            (INVENTORYITEM, "sockets.socketEntries[].reusablePlugItems[].plugItemHash", INVENTORYITEM),
+            (INVENTORYITEM, "sockets.socketEntries[].reusablePlugSetHash", PLUGSET),
+            (INVENTORYITEM, "sockets.socketEntries[].randomizedPlugSetHash", PLUGSET),
+            (INVENTORYITEM, "sockets.socketEntries[].socketTypeHash", SOCKETTYPE),
+            (INVENTORYITEM, "sockets.socketCategories[].socketCategoryHash", SOCKETCATEGORY),
+            (INVENTORYITEM, "stats.statGroupHash", ITEMSTATGROUP),
+            (INVENTORYITEM, "stats.stats.*.statHash", ITEMSTAT),
+            (INVENTORYITEM, "perks[].perkHash", SANDBOXPERK),
+            (INVENTORYITEM, "collectibleHash", COLLECTIBLE),
+            (INVENTORYITEM, "loreHash", LORE),
+            (INVENTORYITEM, "summaryItemHash", INVENTORYITEM),
+            (INVENTORYITEM, "inventory.bucketTypeHash", INVENTORYBUCKET),
+            (INVENTORYITEM, "inventory.tierTypeHash", ITEMTIER),
+            (INVENTORYITEM, "equippingBlock.equipmentSlotTypeHash", EQUIPMENTSLOT),
+            (INVENTORYITEM, "damageTypeHashes[]", DAMAGETYPE),
+            (INVENTORYITEM, "itemCategoryHashes[]", ITEMCATEGORY),
+            (INVENTORYITEM, "traitHashes[]", TRAIT),
+            (INVENTORYITEM, "breakerTypeHash", BREAKERTYPE),
+            (INVENTORYITEM, "seasonHash", SEASON),
+            (INVENTORYITEM, "plug.energyCost.energyTypeHash", ENERGYTYPE),
+            (PLUGSET, "reusablePlugItems[].plugItemHash", INVENTORYITEM),
+            (SOCKETTYPE, "socketCategoryHash", SOCKETCATEGORY),
+            (ITEMSTATGROUP, "scaledStats[].statHash", ITEMSTAT),
+            (SANDBOXPERK, "damageTypeHash", DAMAGETYPE),
+            (COLLECTIBLE, "itemHash", INVENTORYITEM),
+            (COLLECTIBLE, "parentNodeHashes[]", PRESENTATIONNODE),
+            (RECORD, "loreHash", LORE),
+            (RECORD, "objectiveHashes[]", OBJECTIVE),
+            (RECORD, "rewardItems[].itemHash", INVENTORYITEM),
+            (RECORD, "parentNodeHashes[]", PRESENTATIONNODE),
+            (PRESENTATIONNODE, "children.presentationNodes[].presentationNodeHash", PRESENTATIONNODE),
+            (PRESENTATIONNODE, "children.collectibles[].collectibleHash", COLLECTIBLE),
+            (PRESENTATIONNODE, "children.records[].recordHash", RECORD),
+            (PRESENTATIONNODE, "children.metrics[].metricHash", METRIC),
+            (ACTIVITY, "destinationHash", DESTINATION),
+            (ACTIVITY, "placeHash", PLACE),
+            (ACTIVITY, "activityTypeHash", ACTIVITYTYPE),
+            (ACTIVITY, "activityModeHashes[]", ACTIVITYMODE),
+            (ACTIVITY, "modifiers[].activityModifierHash", ACTIVITYMODIFIER),
+            (DESTINATION, "placeHash", PLACE),
+            (VENDOR, "itemList[].itemHash", INVENTORYITEM),
+        ];
+
+        rules.iter().map(|(from, path, to)| ReferenceRule::new(*from, path, *to)).collect()
+    }
+
+    /// Every non-zero hash at this rule's path in a definition
+    pub fn read(&self, def: &Value) -> Vec<i64> {
+        let mut values = vec![def];
+
+        for segment in self.path.split('.') {
+            let (key, each) = match segment.strip_suffix("[]") {
+                Some(key) => (key, true),
+                None => (segment, false),
+            };
+
+            let mut next = vec![];
+
+            for val in values {
+                let inner = if key == "*" {
+                    val.as_object().map(|o| o.values().collect()).unwrap_or_default()
+                } else {
+                    val.get(key).into_iter().collect::<Vec<&Value>>()
+                };
+
+                for v in inner {
+                    if each {
+                        next.extend(v.as_array().map(|a| a.iter().collect()).unwrap_or_else(Vec::new));
+                    } else {
+                        next.push(v);
+                    }
+                }
+            }
+
+            values = next;
+        }
+
+        values.into_iter().filter_map(|v| v.as_i64()).filter(|h| *h != 0).collect()
+    }
+}
+
+/// A definition reached while traversing the graph
+#[derive(Deserialize, Serialize, Clone)]
+pub struct GraphNode {
+    pub entityType: String,
+    pub hash: i64,
+    pub name: String,
+    /// How many references away from the starting definition this is
+    pub depth: u32,
+    /// The definition that referenced this one and the field it was referenced by, None for the start
+    pub parentHash: Option<i64>,
+    pub path: Option<String>,
+    pub definition: Value,
+}
+
+pub struct TraversalOptions {
+    pub max_depth: u32,
+    /// Only visit definitions of these types, or every type if empty
+    pub follow: Vec<ManifestEntityType>,
+}
+
+impl Default for TraversalOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 2,
+            follow: vec![],
+        }
+    }
+}
+
+/// Follows the references between definitions
+///
+/// For example, every perk that can roll on a weapon is reached through its socket
+/// entries, the plug sets they point at, the plugs in those and finally their perks:
+/// find(INVENTORYITEM, weaponHash, SANDBOXPERK, TraversalOptions { max_depth: 3, follow: vec![PLUGSET, INVENTORYITEM, SANDBOXPERK] })
+pub struct ManifestGraph<'a, M: ManifestSource> {
+    manifest: &'a M,
+    rules: Vec<ReferenceRule>,
+}
+
+impl<'a, M: ManifestSource> ManifestGraph<'a, M> {
+    /// A graph using ReferenceRule::defaults()
+    pub fn new(manifest: &'a M) -> Self {
+        Self {
+            manifest,
+            rules: ReferenceRule::defaults(),
+        }
+    }
+
+    /// Follow another reference field as well
+    pub fn with_rule(mut self, rule: ReferenceRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// The definitions a definition references directly, as (type, hash, path)
+    pub fn get_references(&self, typ: ManifestEntityType, def: &Value) -> Vec<(ManifestEntityType, i64, String)> {
+        let mut vec = vec![];
+
+        for rule in self.rules.iter().filter(|r| r.from == typ) {
+            for hash in rule.read(def) {
+                vec.push((rule.to, hash, rule.path.clone()));
+            }
+        }
+
+        vec
+    }
+
+    /// Visit everything reachable from a definition, breadth first, including the definition itself
+    ///
+    /// Every definition is visited once, through the shortest chain of references.
+    /// References to definitions that don't exist are skipped.
+    pub fn traverse(&self, typ: ManifestEntityType, hash: i64, options: &TraversalOptions) -> Result<Vec<GraphNode>> {
+        let start = self.manifest.get_raw(typ, hash)?;
+
+        let mut visited = HashSet::from([(typ.get(), hash as u32)]);
+        let mut queue = VecDeque::from([(typ, hash, start, 0, None, None)]);
+        let mut nodes = vec![];
+
+        while let Some((typ, hash, def, depth, parent_hash, path)) = queue.pop_front() {
+            if depth < options.max_depth {
+                for (next_typ, next_hash, next_path) in self.get_references(typ, &def) {
+                    if !options.follow.is_empty() && !options.follow.contains(&next_typ) {
+                        continue;
+                    }
+
+                    if !visited.insert((next_typ.get(), next_hash as u32)) {
+                        continue;
+                    }
+
+                    if let Ok(next) = self.manifest.get_raw(next_typ, next_hash) {
+                        queue.push_back((next_typ, next_hash, next, depth + 1, Some(hash), Some(next_path)));
+                    }
+                }
+            }
+
+            nodes.push(GraphNode {
+                entityType: typ.get(),
+                hash,
+                name: def["displayProperties"]["name"].as_str().unwrap_or_default().to_string(),
+                depth,
+                parentHash: parent_hash,
+                path,
+                definition: def,
+            });
+        }
+
+        Ok(nodes)
+    }
+
+    /// Every definition of the target type reachable from a definition
+    pub fn find(&self, typ: ManifestEntityType, hash: i64, target: ManifestEntityType, options: &TraversalOptions) -> Result<Vec<GraphNode>> {
+        Ok(self.traverse(typ, hash, options)?.into_iter().filter(|n| n.entityType == target.get() && n.depth > 0).collect())
+    }
+}
+
+#[test]
+fn graph_finds_weapon_perks() {
+    use ManifestEntityType::*;
+
+    let source = TestSource::new(vec![
+        (INVENTORYITEM, serde_json::from_str(r#"[
+            {"hash": 1, "displayProperties": {"name": "Fatebringer"}, "collectibleHash": 9,
+                "sockets": {"socketEntries": [{"singleInitialItemHash": 0, "randomizedPlugSetHash": 10}, {"reusablePlugSetHash": 11}]}},
+            {"hash": 100, "displayProperties": {"name": "Explosive Payload"}, "perks": [{"perkHash": 1000}]},
+            {"hash": 101, "displayProperties": {"name": "Firefly"}, "perks": [{"perkHash": 1001}, {"perkHash": 1002}]}
+        ]"#).unwrap()),
+        (PLUGSET, serde_json::from_str(r#"[
+            {"hash": 10, "reusablePlugItems": [{"plugItemHash": 100}, {"plugItemHash": 101}]},
+            {"hash": 11, "reusablePlugItems": [{"plugItemHash": 101}]}
+        ]"#).unwrap()),
+        (SANDBOXPERK, serde_json::from_str(r#"[
+            {"hash": 1000, "displayProperties": {"name": "Explosive Payload"}},
+            {"hash": 1001, "displayProperties": {"name": "Firefly"}}
+        ]"#).unwrap()),
+        (COLLECTIBLE, serde_json::from_str(r#"[{"hash": 9, "itemHash": 1}]"#).unwrap()),
+    ]);
+
+    let graph = ManifestGraph::new(&source);
+
+    // 1002 doesn't exist and is skipped, 101 is only visited once
+    let perks = graph.find(INVENTORYITEM, 1, SANDBOXPERK, &TraversalOptions { max_depth: 3, follow: vec![PLUGSET, INVENTORYITEM, SANDBOXPERK] }).unwrap();
+    assert_eq!(vec![1001, 1000], perks.iter().map(|n| n.hash).collect::<Vec<i64>>());
+    assert_eq!(3, perks[0].depth);
+    assert_eq!(Some(101), perks[0].parentHash);
+    assert_eq!(Some(String::from("perks[].perkHash")), perks[0].path);
+
+    // The depth limit stops before the perks, and the collectible is reached when every type is followed
+    let nodes = graph.traverse(INVENTORYITEM, 1, &TraversalOptions { max_depth: 2, follow: vec![] }).unwrap();
+    assert_eq!(vec![1, 11, 10, 9, 101, 100], nodes.iter().map(|n| n.hash).collect::<Vec<i64>>());
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use crate::api::ApiClient::ApiClient;
+use crate::api::DestinyAPI::BUNGIE_BASE;
+use crate::api::manifest::definitions::Definition;
+use crate::api::manifest::manifest::{Manifest, ManifestEntityType, ManifestLanguage, ManifestSource};
+
+/// The manifest built from Bungie's per-definition JSON files
+///
+/// Only the definition types that are asked for are downloaded, so a bot that only
+/// needs items and activities doesn't pay for the whole database and doesn't need
+/// SQLite at all. Tables are kept in memory, and optionally cached in a directory
+/// so they don't have to be downloaded again until the manifest changes.
+pub struct JsonManifest {
+    client: ApiClient,
+    path: Option<PathBuf>,
+    language: ManifestLanguage,
+    version: String,
+    /// The content path of every definition type for this language and version
+    paths: HashMap<String, String>,
+    tables: RwLock<HashMap<String, Map<String, Value>>>,
+}
+
+impl JsonManifest {
+    /// Load the given definition types for a language
+    ///
+    /// If a path is given, tables are read from and saved to that directory
+    pub async fn load(client: &ApiClient, language: ManifestLanguage, types: Vec<ManifestEntityType>, path: Option<String>) -> Result<Self> {
+        let info = Manifest::new(client.clone().await).get_manifest_info().await?;
+        let paths = info.jsonWorldComponentContentPaths.get(&language.get()).cloned().ok_or_else(|| anyhow!("The manifest has no JSON components for language {}", language.get()))?;
+
+        let manifest = Self {
+            client: client.clone().await,
+            path: path.map(PathBuf::from),
+            language,
+            version: info.version,
+            paths,
+            tables: RwLock::new(HashMap::new()),
+        };
+
+        for typ in types {
+            manifest.load_type(typ).await?;
+        }
+
+        Ok(manifest)
+    }
+
+    /// Download another definition type, does nothing if it is already loaded
+    pub async fn load_type(&self, typ: ManifestEntityType) -> Result<()> {
+        if self.is_loaded(&typ)? {
+            return Ok(());
+        }
+
+        let content_path = self.paths.get(&typ.get()).ok_or_else(|| anyhow!("The manifest has no JSON component for {}", typ.get()))?;
+        let cached = self.path.as_ref().and_then(|dir| Path::new(content_path).file_name().map(|f| dir.join(f)));
+
+        let json = match &cached {
+            Some(file) if file.exists() => fs::read_to_string(file)?,
+            _ => self.client.get(format!("{}{}", BUNGIE_BASE, content_path)).await?,
+        };
+
+        self.insert_table(&typ, json.as_str())?;
+
+        if let Some(file) = cached {
+            if !file.exists() {
+                if let Some(dir) = file.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+
+                // Write through a temporary file so a crash never leaves a truncated table behind
+                let temp = file.with_extension("download");
+                fs::write(&temp, json)?;
+                fs::rename(temp, file)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_table(&self, typ: &ManifestEntityType, json: &str) -> Result<()> {
+        let table = serde_json::from_str::<Map<String, Value>>(json)?;
+
+        self.tables.write().map_err(|_| anyhow!("Manifest table lock was poisoned"))?.insert(typ.get(), table);
+
+        Ok(())
+    }
+
+    pub fn is_loaded(&self, typ: &ManifestEntityType) -> Result<bool> {
+        Ok(self.tables.read().map_err(|_| anyhow!("Manifest table lock was poisoned"))?.contains_key(&typ.get()))
+    }
+
+    pub fn get_version(&self) -> String {
+        self.version.clone()
+    }
+
+    pub fn get_language(&self) -> ManifestLanguage {
+        self.language
+    }
+
+    /// Get the JSON definition of an entity, the type must have been loaded first
+    pub fn get_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Value> {
+        self.find_raw(typ, hash)?.ok_or_else(|| anyhow!("No {} was found with the hash {}", typ.get(), hash))
+    }
+
+    /// Get the JSON definition of an entity, None if the loaded type has no entity with that hash
+    pub fn find_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Option<Value>> {
+        let tables = self.tables.read().map_err(|_| anyhow!("Manifest table lock was poisoned"))?;
+        let table = tables.get(&typ.get()).ok_or_else(|| anyhow!("{} has not been loaded, call load_type() first", typ.get()))?;
+
+        // The files are keyed by the unsigned hash, but accept the signed ids used by the database as well
+        Ok(table.get(&(hash as u32).to_string()).cloned())
+    }
+
+    /// Get the JSON definition of every entity of a type, the type must have been loaded first
+    pub fn get_all_raw(&self, typ: ManifestEntityType) -> Result<Vec<Value>> {
+        let tables = self.tables.read().map_err(|_| anyhow!("Manifest table lock was poisoned"))?;
+        let table = tables.get(&typ.get()).ok_or_else(|| anyhow!("{} has not been loaded, call load_type() first", typ.get()))?;
+
+        Ok(table.values().cloned().collect())
+    }
+
+    /// Get a typed definition, e.g. get::<DestinyInventoryItemDefinition>(hash)
+    pub fn get<T: Definition>(&self, hash: i64) -> Result<T> {
+        ManifestSource::get::<T>(self, hash)
+    }
+}
+
+impl ManifestSource for JsonManifest {
+    fn get_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Value> {
+        JsonManifest::get_raw(self, typ, hash)
+    }
+
+    fn find_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Option<Value>> {
+        JsonManifest::find_raw(self, typ, hash)
+    }
+
+    fn get_all_raw(&self, typ: ManifestEntityType) -> Result<Vec<Value>> {
+        JsonManifest::get_all_raw(self, typ)
+    }
+
+    fn has_type(&self, typ: ManifestEntityType) -> Result<bool> {
+        self.is_loaded(&typ)
+    }
+
+    fn get_language(&self) -> ManifestLanguage {
+        self.language
+    }
+}
+
+#[test]
+fn json_manifest_lookup() {
+    let manifest = JsonManifest {
+        client: ApiClient::new(""),
+        path: None,
+        language: ManifestLanguage::English,
+        version: String::from("test"),
+        paths: HashMap::new(),
+        tables: RwLock::new(HashMap::new()),
+    };
+
+    manifest.insert_table(&ManifestEntityType::ACTIVITY, r#"{"3881495763": {"hash": 3881495763, "displayProperties": {"name": "Vault of Glass"}}}"#).unwrap();
+
+    assert!(manifest.is_loaded(&ManifestEntityType::ACTIVITY).unwrap());
+    assert_eq!("Vault of Glass", manifest.get_raw(ManifestEntityType::ACTIVITY, 3881495763).unwrap()["displayProperties"]["name"]);
+    assert_eq!("Vault of Glass", manifest.get_raw(ManifestEntityType::ACTIVITY, -413471533).unwrap()["displayProperties"]["name"]);
+    assert!(manifest.get_raw(ManifestEntityType::ACTIVITY, 910380154).is_err());
+    assert!(manifest.get_raw(ManifestEntityType::INVENTORYITEM, 3881495763).is_err());
+}
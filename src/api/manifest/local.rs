@@ -0,0 +1,461 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlite::{Connection, State};
+use tokio::task::JoinHandle;
+use zip::ZipArchive;
+use crate::api::ApiClient::ApiClient;
+use crate::api::DestinyAPI::BUNGIE_BASE;
+use crate::api::manifest::definitions::Definition;
+use crate::api::manifest::diff::ManifestDiff;
+use crate::api::manifest::localized::LocalizedManifest;
+#[cfg(test)]
+use crate::api::manifest::definitions::DestinyActivityDefinition;
+use crate::api::manifest::manifest::{Manifest, ManifestEntityType, ManifestInfoResponse, ManifestLanguage, ManifestSource};
+
+/// A copy of the manifest's SQLite database stored on disk
+///
+/// The database for a language is downloaded the first time it is loaded, after
+/// which every lookup is served locally instead of costing a request. The installed
+/// version is recorded in a small metadata file next to the database so it can be
+/// updated in place when Bungie publishes a new manifest.
+pub struct LocalManifest {
+    client: ApiClient,
+    path: PathBuf,
+    language: ManifestLanguage,
+    connection: Mutex<Connection>,
+    metadata: Mutex<ManifestMetadata>,
+    /// Held for a whole update, so overlapping updates don't install the same version twice
+    updating: tokio::sync::Mutex<()>,
+}
+
+/// What is currently installed for a language, stored as manifest-{language}.json
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ManifestMetadata {
+    pub version: String,
+    pub language: String,
+    pub file: String,
+    /// The version that was installed before the current one, kept for rollback
+    pub previousVersion: Option<String>,
+    pub previousFile: Option<String>,
+}
+
+impl LocalManifest {
+    /// Load the manifest for a language from the given directory, updating it if there is a newer version
+    ///
+    /// If nothing has been installed in the directory yet the current version is
+    /// downloaded and unzipped there first.
+    pub async fn load(client: &ApiClient, path: String, language: ManifestLanguage) -> Result<Self> {
+        let manifest = match LocalManifest::open_installed(client, path.clone(), language).await? {
+            Some(manifest) => manifest,
+            None => {
+                let info = Manifest::new(client.clone().await).get_manifest_info().await?;
+                let file = LocalManifest::download(client, Path::new(&path), &info, &language).await?;
+
+                let metadata = ManifestMetadata {
+                    version: info.version,
+                    language: language.get(),
+                    file,
+                    previousVersion: None,
+                    previousFile: None,
+                };
+
+                let manifest = LocalManifest::open(client.clone().await, path, language, metadata)?;
+                manifest.save_metadata()?;
+                manifest
+            }
+        };
+
+        manifest.update().await?;
+
+        Ok(manifest)
+    }
+
+    /// Load several languages side by side in the same directory, see load()
+    ///
+    /// Each language has its own database and metadata file, so they can be updated independently
+    pub async fn load_languages(client: &ApiClient, path: String, languages: Vec<ManifestLanguage>) -> Result<LocalizedManifest<LocalManifest>> {
+        let mut manifests = LocalizedManifest::new(vec![]);
+
+        for language in languages {
+            manifests.insert(LocalManifest::load(client, path.clone(), language).await?);
+        }
+
+        Ok(manifests)
+    }
+
+    /// Open whatever version is installed in the directory without checking for updates
+    ///
+    /// Returns None if nothing has been installed for this language yet
+    pub async fn open_installed(client: &ApiClient, path: String, language: ManifestLanguage) -> Result<Option<Self>> {
+        let metadata_file = LocalManifest::metadata_file(Path::new(&path), &language);
+
+        if !metadata_file.exists() {
+            return Ok(None);
+        }
+
+        let metadata = serde_json::from_str::<ManifestMetadata>(fs::read_to_string(metadata_file)?.as_str())?;
+
+        if !Path::new(&path).join(&metadata.file).exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(LocalManifest::open(client.clone().await, path, language, metadata)?))
+    }
+
+    /// Open the database described by the metadata in the given directory
+    pub fn open(client: ApiClient, path: String, language: ManifestLanguage, metadata: ManifestMetadata) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let connection = Connection::open(path.join(&metadata.file))?;
+
+        Ok(Self {
+            client,
+            path,
+            language,
+            connection: Mutex::new(connection),
+            metadata: Mutex::new(metadata),
+            updating: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn get_version(&self) -> Result<String> {
+        Ok(self.get_metadata()?.version)
+    }
+
+    pub fn get_metadata(&self) -> Result<ManifestMetadata> {
+        Ok(self.metadata.lock().map_err(|_| anyhow!("Manifest metadata lock was poisoned"))?.clone())
+    }
+
+    /// Check whether Bungie has published a different version than the one installed
+    pub async fn is_outdated(&self) -> Result<bool> {
+        let info = Manifest::new(self.client.clone().await).get_manifest_info().await?;
+
+        Ok(info.version != self.get_version()?)
+    }
+
+    /// Download and switch to the current version if it differs from the installed one
+    ///
+    /// Lookups keep being served from the old database while the new one downloads.
+    /// The old version is kept on disk for rollback() and anything older is deleted.
+    /// Returns true if a new version was installed.
+    pub async fn update(&self) -> Result<bool> {
+        let _updating = self.updating.lock().await;
+        let info = Manifest::new(self.client.clone().await).get_manifest_info().await?;
+
+        if info.version == self.get_version()? {
+            return Ok(false);
+        }
+
+        let file = LocalManifest::download(&self.client, &self.path, &info, &self.language).await?;
+        self.install(info.version, file)?;
+
+        Ok(true)
+    }
+
+    /// Check for updates in the background every interval until the manifest is dropped
+    ///
+    /// Failed checks are ignored and retried on the next interval
+    pub fn auto_update(manifest: Arc<LocalManifest>, interval: Duration) -> JoinHandle<()> {
+        let manifest = Arc::downgrade(&manifest);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                match manifest.upgrade() {
+                    Some(manifest) => {
+                        let _ = manifest.update().await;
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+
+    /// Switch back to the version that was installed before the last update
+    pub fn rollback(&self) -> Result<()> {
+        let metadata = self.get_metadata()?;
+
+        match (metadata.previousVersion, metadata.previousFile) {
+            (Some(version), Some(file)) => self.install(version, file),
+            _ => Err(anyhow!("There is no previous manifest version to roll back to")),
+        }
+    }
+
+    /// Open the version that was installed before the current one, if it is still on disk
+    pub async fn open_previous(&self) -> Result<Option<LocalManifest>> {
+        let metadata = self.get_metadata()?;
+
+        let (version, file) = match (metadata.previousVersion, metadata.previousFile) {
+            (Some(version), Some(file)) => (version, file),
+            _ => return Ok(None),
+        };
+
+        if !self.path.join(&file).exists() {
+            return Ok(None);
+        }
+
+        let previous = ManifestMetadata {
+            version,
+            language: metadata.language,
+            file,
+            previousVersion: None,
+            previousFile: None,
+        };
+
+        Ok(Some(LocalManifest::open(self.client.clone().await, self.path.to_string_lossy().to_string(), self.language, previous)?))
+    }
+
+    /// Compare the given definition types between the previous version and the current one
+    ///
+    /// Fails if there is no previous version on disk, i.e. the manifest hasn't been updated yet
+    pub async fn diff_previous(&self, types: Vec<ManifestEntityType>) -> Result<ManifestDiff> {
+        let previous = self.open_previous().await?.ok_or_else(|| anyhow!("There is no previous manifest version to compare against"))?;
+
+        let mut diff = ManifestDiff::compare(&previous, self, types)?;
+        diff.oldVersion = previous.get_version()?;
+        diff.newVersion = self.get_version()?;
+
+        Ok(diff)
+    }
+
+    /// Swap the open database for another file in the directory and record it as the current version
+    ///
+    /// Does nothing if that version is already installed, so the previous version is never lost
+    fn install(&self, version: String, file: String) -> Result<()> {
+        if version == self.get_version()? {
+            return Ok(());
+        }
+
+        let connection = Connection::open(self.path.join(&file))?;
+
+        let old = {
+            let mut metadata = self.metadata.lock().map_err(|_| anyhow!("Manifest metadata lock was poisoned"))?;
+            let old = metadata.clone();
+
+            metadata.previousVersion = Some(old.version.clone());
+            metadata.previousFile = Some(old.file.clone());
+            metadata.version = version;
+            metadata.file = file.clone();
+
+            old
+        };
+
+        *self.connection.lock().map_err(|_| anyhow!("Manifest connection lock was poisoned"))? = connection;
+        self.save_metadata()?;
+
+        // Only the current and previous versions are kept around
+        if let Some(oldest) = old.previousFile {
+            if oldest != file && oldest != old.file {
+                let _ = fs::remove_file(self.path.join(oldest));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the metadata through a temporary file so it is never left half written
+    fn save_metadata(&self) -> Result<()> {
+        let file = LocalManifest::metadata_file(&self.path, &self.language);
+        let temp = file.with_extension("json.tmp");
+
+        fs::write(&temp, serde_json::to_string_pretty(&self.get_metadata()?)?)?;
+        fs::rename(temp, file)?;
+
+        Ok(())
+    }
+
+    fn metadata_file(path: &Path, language: &ManifestLanguage) -> PathBuf {
+        path.join(format!("manifest-{}.json", language.get()))
+    }
+
+    /// Download the zipped database for a language into the directory, returning the name of the extracted file
+    async fn download(client: &ApiClient, path: &Path, info: &ManifestInfoResponse, language: &ManifestLanguage) -> Result<String> {
+        let content_path = info.mobileWorldContentPaths.get(language);
+        let file_name = Path::new(content_path.as_str()).file_name().and_then(|f| f.to_str()).ok_or_else(|| anyhow!("Manifest content path {} has no file name", content_path))?.to_string();
+        let file = path.join(&file_name);
+
+        if file.exists() {
+            return Ok(file_name);
+        }
+
+        fs::create_dir_all(path)?;
+
+        let bytes = client.get_bytes(format!("{}{}", BUNGIE_BASE, content_path)).await?;
+
+        let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+        let mut content = archive.by_index(0)?;
+
+        // Extract next to the destination first so a failed download never leaves a partial database behind
+        let temp = file.with_extension("download");
+        io::copy(&mut content, &mut File::create(&temp)?)?;
+        fs::rename(&temp, &file)?;
+
+        Ok(file_name)
+    }
+
+    /// Get the JSON definition of an entity, see Manifest::manifest_value()
+    pub fn get_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Value> {
+        self.find_raw(typ, hash)?.ok_or_else(|| anyhow!("No {} was found with the hash {}", typ.get(), hash))
+    }
+
+    /// Get the JSON definition of an entity, None if there is no entity with that hash
+    pub fn find_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Option<Value>> {
+        let connection = self.connection.lock().map_err(|_| anyhow!("Manifest connection lock was poisoned"))?;
+
+        let mut statement = connection.prepare(format!("SELECT json FROM {} WHERE id = ?", typ.get()))?;
+        statement.bind(1, LocalManifest::hash_to_id(hash))?;
+
+        if let State::Row = statement.next()? {
+            return Ok(Some(serde_json::from_str::<Value>(statement.read::<String>(0)?.as_str())?));
+        }
+
+        Ok(None)
+    }
+
+    /// Get the JSON definition of every entity of a type
+    pub fn get_all_raw(&self, typ: ManifestEntityType) -> Result<Vec<Value>> {
+        let connection = self.connection.lock().map_err(|_| anyhow!("Manifest connection lock was poisoned"))?;
+
+        let mut statement = connection.prepare(format!("SELECT json FROM {}", typ.get()))?;
+        let mut vec = vec![];
+
+        while let State::Row = statement.next()? {
+            vec.push(serde_json::from_str::<Value>(statement.read::<String>(0)?.as_str())?);
+        }
+
+        Ok(vec)
+    }
+
+    pub fn get_language(&self) -> ManifestLanguage {
+        self.language
+    }
+
+    /// Whether the database has a table for the type, older manifests lack newer definition types
+    pub fn has_type(&self, typ: ManifestEntityType) -> Result<bool> {
+        let connection = self.connection.lock().map_err(|_| anyhow!("Manifest connection lock was poisoned"))?;
+
+        let mut statement = connection.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")?;
+        statement.bind(1, typ.get().as_str())?;
+
+        Ok(matches!(statement.next()?, State::Row))
+    }
+
+    /// Get a typed definition, e.g. get::<DestinyInventoryItemDefinition>(hash)
+    pub fn get<T: Definition>(&self, hash: i64) -> Result<T> {
+        ManifestSource::get::<T>(self, hash)
+    }
+
+    /// The database stores hashes, which are unsigned 32 bit numbers, as signed 32 bit ids
+    pub fn hash_to_id(hash: i64) -> i64 {
+        hash as u32 as i32 as i64
+    }
+}
+
+impl ManifestSource for LocalManifest {
+    fn get_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Value> {
+        LocalManifest::get_raw(self, typ, hash)
+    }
+
+    fn find_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Option<Value>> {
+        LocalManifest::find_raw(self, typ, hash)
+    }
+
+    fn get_all_raw(&self, typ: ManifestEntityType) -> Result<Vec<Value>> {
+        LocalManifest::get_all_raw(self, typ)
+    }
+
+    fn has_type(&self, typ: ManifestEntityType) -> Result<bool> {
+        LocalManifest::has_type(self, typ)
+    }
+
+    fn get_language(&self) -> ManifestLanguage {
+        self.language
+    }
+}
+
+#[cfg(test)]
+fn test_database(dir: &Path, file: &str, hash: i64, name: &str) {
+    let connection = Connection::open(dir.join(file)).unwrap();
+    connection.execute("CREATE TABLE DestinyActivityDefinition (id INTEGER PRIMARY KEY NOT NULL, json BLOB)").unwrap();
+    connection.execute(format!("INSERT INTO DestinyActivityDefinition VALUES ({}, '{{\"hash\": {}, \"displayProperties\": {{\"name\": \"{}\"}}}}')", LocalManifest::hash_to_id(hash), hash, name)).unwrap();
+}
+
+#[cfg(test)]
+fn test_manifest(dir: &str) -> (PathBuf, LocalManifest) {
+    let dir = std::env::temp_dir().join(dir);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    test_database(&dir, "first.content", 3881495763, "Vault of Glass");
+
+    let metadata = ManifestMetadata {
+        version: String::from("1"),
+        language: ManifestLanguage::English.get(),
+        file: String::from("first.content"),
+        previousVersion: None,
+        previousFile: None,
+    };
+
+    let manifest = LocalManifest::open(ApiClient::new(""), dir.to_str().unwrap().to_string(), ManifestLanguage::English, metadata).unwrap();
+
+    (dir, manifest)
+}
+
+#[test]
+fn local_manifest_signed_ids() {
+    let (dir, manifest) = test_manifest("rustiny_local_manifest_signed_ids");
+
+    assert_eq!(-413471533, LocalManifest::hash_to_id(3881495763));
+    assert_eq!(910380154, LocalManifest::hash_to_id(910380154));
+    assert_eq!(3881495763, manifest.get_raw(ManifestEntityType::ACTIVITY, 3881495763).unwrap()["hash"].as_i64().unwrap());
+    assert!(manifest.get_raw(ManifestEntityType::ACTIVITY, 910380154).is_err());
+    assert_eq!("Vault of Glass", manifest.get::<DestinyActivityDefinition>(3881495763).unwrap().displayProperties.name);
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn local_manifest_install_and_rollback() {
+    let (dir, manifest) = test_manifest("rustiny_local_manifest_install");
+    test_database(&dir, "second.content", 910380154, "Deep Stone Crypt");
+    test_database(&dir, "third.content", 1441982566, "Vow of the Disciple");
+
+    manifest.install(String::from("2"), String::from("second.content")).unwrap();
+    assert_eq!("2", manifest.get_version().unwrap());
+
+    // Installing the same version again, as an overlapping update would, keeps the previous one
+    manifest.install(String::from("2"), String::from("second.content")).unwrap();
+    assert_eq!(Some(String::from("1")), manifest.get_metadata().unwrap().previousVersion);
+    assert!(dir.join("first.content").exists());
+    assert!(manifest.get_raw(ManifestEntityType::ACTIVITY, 910380154).is_ok());
+    assert!(manifest.get_raw(ManifestEntityType::ACTIVITY, 3881495763).is_err());
+
+    // The metadata on disk should now point at the second version
+    let reopened = LocalManifest::open_installed(&ApiClient::new(""), dir.to_str().unwrap().to_string(), ManifestLanguage::English).await.unwrap().unwrap();
+    assert_eq!("2", reopened.get_version().unwrap());
+    assert_eq!(Some(String::from("1")), reopened.get_metadata().unwrap().previousVersion);
+
+    let diff = manifest.diff_previous(vec![ManifestEntityType::ACTIVITY]).await.unwrap();
+    assert_eq!(("1", "2"), (diff.oldVersion.as_str(), diff.newVersion.as_str()));
+    assert_eq!("Deep Stone Crypt", diff.tables[0].added[0].name);
+    assert_eq!("Vault of Glass", diff.tables[0].removed[0].name);
+
+    manifest.rollback().unwrap();
+    assert_eq!("1", manifest.get_version().unwrap());
+    assert!(manifest.get_raw(ManifestEntityType::ACTIVITY, 3881495763).is_ok());
+
+    // Installing a third version should delete whatever is older than the previous one
+    manifest.install(String::from("3"), String::from("third.content")).unwrap();
+    assert!(dir.join("first.content").exists());
+    assert!(!dir.join("second.content").exists());
+
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use crate::api::manifest::definitions::Definition;
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestLanguage, ManifestSource};
+#[cfg(test)]
+use crate::api::manifest::manifest::TestSource;
+
+/// Several languages of the same manifest kept side by side
+///
+/// Lookups take a chain of languages and return the definition from the first
+/// language that has it translated, so a bot can answer each user in their own
+/// language and still fall back to English for anything that is missing.
+pub struct LocalizedManifest<M: ManifestSource> {
+    manifests: Vec<M>,
+}
+
+impl<M: ManifestSource> LocalizedManifest<M> {
+    /// Combine manifests in different languages, if a language is given twice the first one is used
+    pub fn new(manifests: Vec<M>) -> Self {
+        Self {
+            manifests,
+        }
+    }
+
+    /// Add another language, replacing the manifest for that language if there already is one
+    pub fn insert(&mut self, manifest: M) {
+        let language = manifest.get_language();
+
+        self.manifests.retain(|m| m.get_language() != language);
+        self.manifests.push(manifest);
+    }
+
+    pub fn get_manifest(&self, language: ManifestLanguage) -> Option<&M> {
+        self.manifests.iter().find(|m| m.get_language() == language)
+    }
+
+    pub fn get_manifests(&self) -> &Vec<M> {
+        &self.manifests
+    }
+
+    pub fn get_languages(&self) -> Vec<ManifestLanguage> {
+        self.manifests.iter().map(|m| m.get_language()).collect()
+    }
+
+    /// Get the JSON definition of an entity in the first language of the chain that has it translated
+    ///
+    /// Languages that aren't loaded or don't have the entity are skipped, but any other error
+    /// is returned. If no language has a name for the entity, the definition from the first
+    /// language that has it at all is returned.
+    pub fn get_raw(&self, typ: ManifestEntityType, hash: i64, languages: &[ManifestLanguage]) -> Result<Value> {
+        let mut fallback = None;
+
+        for language in languages {
+            if let Some(manifest) = self.get_manifest(*language) {
+                if let Some(val) = manifest.find_raw(typ, hash)? {
+                    if is_localized(&val) {
+                        return Ok(val);
+                    }
+
+                    fallback.get_or_insert(val);
+                }
+            }
+        }
+
+        fallback.ok_or_else(|| anyhow!("No {} was found with the hash {} in any of the requested languages", typ.get(), hash))
+    }
+
+    /// Get a typed definition in the first language of the chain that has it translated
+    pub fn get<T: Definition>(&self, hash: i64, languages: &[ManifestLanguage]) -> Result<T> {
+        Ok(serde_json::from_value::<T>(self.get_raw(T::entity_type(), hash, languages)?)?)
+    }
+
+    /// Get a typed definition in a language, falling back along ManifestLanguage::fallback_chain()
+    pub fn get_in<T: Definition>(&self, hash: i64, language: ManifestLanguage) -> Result<T> {
+        self.get::<T>(hash, &language.fallback_chain())
+    }
+}
+
+/// Whether a definition has been translated, i.e. it has no display properties or its name isn't empty
+pub fn is_localized(val: &Value) -> bool {
+    match val["displayProperties"]["name"].as_str() {
+        Some(name) => !name.trim().is_empty(),
+        None => val["displayProperties"].is_null(),
+    }
+}
+
+#[test]
+fn localized_fallback_chain() {
+    let manifest = LocalizedManifest::new(vec![
+        TestSource::new(vec![(ManifestEntityType::ACTIVITY, serde_json::from_str(r#"[{"hash": 1, "displayProperties": {"name": "Vault of Glass"}}, {"hash": 2, "displayProperties": {"name": "Deep Stone Crypt"}}]"#).unwrap())]),
+        TestSource::new(vec![(ManifestEntityType::ACTIVITY, serde_json::from_str(r#"[{"hash": 1, "displayProperties": {"name": "Cámara de Cristal"}}, {"hash": 2, "displayProperties": {"name": ""}}]"#).unwrap())]).with_language(ManifestLanguage::Espanol),
+    ]);
+
+    let name = |hash: i64, language: ManifestLanguage| manifest.get_raw(ManifestEntityType::ACTIVITY, hash, &language.fallback_chain()).unwrap()["displayProperties"]["name"].as_str().unwrap().to_string();
+
+    // Portuguese isn't loaded, so Spanish is used, and English where Spanish has no translation
+    assert_eq!("Cámara de Cristal", name(1, ManifestLanguage::PortugueseBrazil));
+    assert_eq!("Deep Stone Crypt", name(2, ManifestLanguage::PortugueseBrazil));
+    assert_eq!("Vault of Glass", name(1, ManifestLanguage::English));
+    assert!(manifest.get_raw(ManifestEntityType::ACTIVITY, 3, &[ManifestLanguage::English]).is_err());
+
+    // A broken database is an error rather than a missing translation
+    let broken = LocalizedManifest::new(vec![TestSource::broken().with_language(ManifestLanguage::Espanol), TestSource::new(vec![(ManifestEntityType::ACTIVITY, vec![])])]);
+    assert!(broken.get_raw(ManifestEntityType::ACTIVITY, 1, &ManifestLanguage::Espanol.fallback_chain()).is_err());
+
+    assert!(ManifestLanguage::from_locale("pt_BR") == Some(ManifestLanguage::PortugueseBrazil));
+    assert!(ManifestLanguage::from_locale("fr-CA") == Some(ManifestLanguage::French));
+    assert!(ManifestLanguage::from_locale("zh-TW") == Some(ManifestLanguage::ChineseTraditional));
+    assert!(ManifestLanguage::from_locale("xx").is_none());
+}
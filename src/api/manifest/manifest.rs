@@ -1,86 +1,147 @@
+use std::collections::HashMap;
 use crate::api::ApiClient::ApiClient;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde_json::Value;
 use crate::api::DestinyAPI::URL_BASE;
 use serde::{Deserialize, Serialize};
-use sqlite::Connection;
-use crate::enumize;
+use crate::api::manifest::definitions::{Definition, DestinyMilestoneDefinition, MilestoneRewardEntry};
+use crate::{enumize, map};
+use crate::api::manifest::localized::is_localized;
+#[cfg(feature = "sqlite")]
+pub use crate::api::manifest::local::{LocalManifest, ManifestMetadata};
 
 pub struct Manifest {
     client: ApiClient,
+    language: Option<ManifestLanguage>,
 }
 
 impl Manifest {
     pub fn new(client: ApiClient) -> Self {
         Self {
             client,
+            language: None,
         }
     }
 
+    /// Request definitions in a language instead of Bungie's default of English
+    pub fn with_language(mut self, language: ManifestLanguage) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    pub fn get_language(&self) -> ManifestLanguage {
+        self.language.unwrap_or(ManifestLanguage::English)
+    }
+
     pub async fn manifest(&self, typ: ManifestEntityType, hash: i64) -> Result<String> {
-        let resp = self.client.get(format!("{}/Destiny2/Manifest/{entityType}/{hashIdentifier}/", URL_BASE, entityType = typ.get(), hashIdentifier = hash)).await?;
+        self.manifest_get(typ, hash.to_string()).await
+    }
 
-        Ok(resp)
+    /// Get the "Response" of a manifest entity as JSON
+    pub async fn manifest_value(&self, typ: ManifestEntityType, hash: i64) -> Result<Value> {
+        self.manifest_value_in(typ, hash, self.get_language()).await
     }
 
-    pub async fn manifest_get(&self, typ: ManifestEntityType, hash: String) -> Result<String> {
-        let resp = self.client.get(format!("{}/Destiny2/Manifest/{entityType}/{hashIdentifier}/", URL_BASE, entityType = typ.get(), hashIdentifier = hash)).await?;
+    /// Get the "Response" of a manifest entity as JSON in a specific language
+    pub async fn manifest_value_in(&self, typ: ManifestEntityType, hash: i64, language: ManifestLanguage) -> Result<Value> {
+        let lc = language.get();
 
-        Ok(resp)
+        self.client.get_parse_params::<Value>(format!("{}/Destiny2/Manifest/{entityType}/{hashIdentifier}/", URL_BASE, entityType = typ.get(), hashIdentifier = hash), true, map! { "lc" => lc.as_str() }).await
     }
 
-    pub async fn get_manifest_info(&self) -> Result<ManifestInfoResponse> {
-        self.client.get_parse::<ManifestInfoResponse>(format!("{}/Destiny2/Manifest/", URL_BASE), true).await
+    /// Get the "Response" of a manifest entity in a language, None if Bungie has no such entity
+    ///
+    /// Unlike manifest_value_in(), request and API errors are returned rather than an empty response
+    pub async fn find_value_in(&self, typ: ManifestEntityType, hash: i64, language: ManifestLanguage) -> Result<Option<Value>> {
+        let lc = language.get();
+        let val = self.client.get_parse_params::<Value>(format!("{}/Destiny2/Manifest/{entityType}/{hashIdentifier}/", URL_BASE, entityType = typ.get(), hashIdentifier = hash), false, map! { "lc" => lc.as_str() }).await?;
+
+        if val["ErrorStatus"].as_str() == Some("DestinyEntityNotFound") {
+            return Ok(None);
+        }
+
+        ApiClient::check_error_code(&val)?;
+
+        Ok(match &val["Response"] {
+            Value::Null => None,
+            response => Some(response.clone()),
+        })
     }
 
-    pub async fn manifest_reward(&self, milestoneHash: i64, rewardEntryHash: i64) -> Result<RewardInfo> {
-        let resp = serde_json::from_str::<Value>(self.manifest(ManifestEntityType::MILESTONE, milestoneHash).await?.as_str())?;
+    /// Get a typed definition, e.g. get::<DestinyInventoryItemDefinition>(hash)
+    pub async fn get<T: Definition>(&self, hash: i64) -> Result<T> {
+        Ok(serde_json::from_value::<T>(self.manifest_value(T::entity_type(), hash).await?)?)
+    }
 
-        let resp = resp["Response"]["rewards"].clone();
-        let resp = serde_json::from_value::<Rewards>(resp)?;
+    /// Get a typed definition in the first language of the chain that has it translated
+    ///
+    /// See ManifestLanguage::fallback_chain()
+    pub async fn get_localized<T: Definition>(&self, hash: i64, languages: &[ManifestLanguage]) -> Result<T> {
+        let mut fallback = None;
 
-        let inner = match rewardEntryHash {
-            // Current Week
-            3789021730 => { resp.currentWeek.rewardEntries.nightfall.unwrap() },
-            248695599 => { resp.currentWeek.rewardEntries.gambit.unwrap() },
-            2043403989 => { resp.currentWeek.rewardEntries.raid.unwrap() },
-            964120289 => { resp.currentWeek.rewardEntries.pvp.unwrap() }
+        for language in languages {
+            if let Some(val) = self.find_value_in(T::entity_type(), hash, *language).await? {
+                if is_localized(&val) {
+                    return Ok(serde_json::from_value::<T>(val)?);
+                }
 
-            // Previous week
-            305996677 => { resp.previousWeek.rewardEntries.PWnightfall.unwrap() },
-            1514402550 => { resp.previousWeek.rewardEntries.PWgambit.unwrap() },
-            783563440 => { resp.previousWeek.rewardEntries.PWraid.unwrap() },
-            1478801436 => { resp.previousWeek.rewardEntries.PWpvp.unwrap() },
-            _ => {
-                panic!("Unknown ID Of Clan Weekly Reward Info");
+                fallback.get_or_insert(val);
             }
-        };
+        }
 
-        Ok(inner)
+        match fallback {
+            Some(val) => Ok(serde_json::from_value::<T>(val)?),
+            None => Err(anyhow!("No {} was found with the hash {} in any of the requested languages", T::entity_type().get(), hash)),
+        }
     }
-}
 
-/// This is where the Local Manifest Manager will live in the future
-/// 
-/// Non-functional at the moment, use Manifest::manifest()
-pub struct LocalManifest {
-    client: ApiClient,
-    connection: Connection,
+    pub async fn manifest_get(&self, typ: ManifestEntityType, hash: String) -> Result<String> {
+        let lc = self.get_language().get();
+        let resp = self.client.get_params(format!("{}/Destiny2/Manifest/{entityType}/{hashIdentifier}/", URL_BASE, entityType = typ.get(), hashIdentifier = hash), map! { "lc" => lc.as_str() }).await?;
+
+        Ok(resp)
+    }
+
+    pub async fn get_manifest_info(&self) -> Result<ManifestInfoResponse> {
+        self.client.get_parse::<ManifestInfoResponse>(format!("{}/Destiny2/Manifest/", URL_BASE), true).await
+    }
+
+    /// Get a reward entry of a milestone, such as one of the clan weekly rewards
+    pub async fn manifest_reward(&self, milestone_hash: i64, reward_entry_hash: i64) -> Result<MilestoneRewardEntry> {
+        let milestone = self.get::<DestinyMilestoneDefinition>(milestone_hash).await?;
+
+        milestone.get_reward_entry(reward_entry_hash).cloned().ok_or_else(|| anyhow!("Milestone {} has no reward entry {}", milestone_hash, reward_entry_hash))
+    }
 }
 
-impl LocalManifest {
-    pub async fn load(client: &ApiClient, path: String, version: String) -> Result<Self> {
-        let info = Manifest::new(client.clone().await).get_manifest_info().await?;
+/// Anything definitions can be looked up from without making a request,
+/// such as the SQLite LocalManifest or the JSON JsonManifest
+pub trait ManifestSource {
+    /// Get the JSON definition of an entity
+    fn get_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Value>;
+
+    /// Get the JSON definition of an entity, None if the source has no entity with that hash
+    ///
+    /// Sources that can't tell a missing entity apart from a failure return every error
+    fn find_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Option<Value>> {
+        self.get_raw(typ, hash).map(Some)
+    }
 
-        let connection = Connection::open(&path)?;
+    /// Get the JSON definition of every entity of a type
+    fn get_all_raw(&self, typ: ManifestEntityType) -> Result<Vec<Value>>;
 
-        Ok(Self {
-            client: client.clone().await,
-            connection,
-        })
+    /// Whether this source has a table for the type at all, as opposed to failing to read it
+    fn has_type(&self, _typ: ManifestEntityType) -> Result<bool> {
+        Ok(true)
     }
 
+    /// The language the definitions are in
+    fn get_language(&self) -> ManifestLanguage;
 
+    /// Get a typed definition, e.g. get::<DestinyInventoryItemDefinition>(hash)
+    fn get<T: Definition>(&self, hash: i64) -> Result<T> where Self: Sized {
+        Ok(serde_json::from_value::<T>(self.get_raw(T::entity_type(), hash)?)?)
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -88,6 +149,12 @@ pub struct ManifestInfoResponse {
     pub version: String,
     pub mobileAssetContentPath: String,
     pub mobileWorldContentPaths: MobileWorldContentPaths,
+    /// The whole manifest as a single JSON file, keyed by language
+    #[serde(default)]
+    pub jsonWorldContentPaths: HashMap<String, String>,
+    /// One JSON file per definition type, keyed by language and then definition name
+    #[serde(default)]
+    pub jsonWorldComponentContentPaths: HashMap<String, HashMap<String, String>>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -111,6 +178,26 @@ pub struct MobileWorldContentPaths {
     pub zhchs: String,
 }
 
+impl MobileWorldContentPaths {
+    pub fn get(&self, language: &ManifestLanguage) -> String {
+        match language {
+            ManifestLanguage::English => self.en.clone(),
+            ManifestLanguage::French => self.fr.clone(),
+            ManifestLanguage::Espanol => self.es.clone(),
+            ManifestLanguage::EspanolMexico => self.esmx.clone(),
+            ManifestLanguage::Deutsch => self.de.clone(),
+            ManifestLanguage::Italian => self.it.clone(),
+            ManifestLanguage::Japanese => self.ja.clone(),
+            ManifestLanguage::PortugueseBrazil => self.ptbr.clone(),
+            ManifestLanguage::Russian => self.ru.clone(),
+            ManifestLanguage::Polish => self.pl.clone(),
+            ManifestLanguage::Korean => self.ko.clone(),
+            ManifestLanguage::ChineseTraditional => self.zhcht.clone(),
+            ManifestLanguage::ChineseSimplified => self.zhchs.clone(),
+        }
+    }
+}
+
 enumize!(ManifestLanguage, String => {
     English, "en".to_string(),
     French, "fr".to_string(),
@@ -127,64 +214,48 @@ enumize!(ManifestLanguage, String => {
     ChineseSimplified, "zh-chs".to_string()
 });
 
-#[derive(Deserialize, Serialize, Clone)]
-pub struct Rewards {
-    #[serde(rename = "1064137897")]
-    pub currentWeek: RewardGroup,
-    #[serde(rename = "4258746474")]
-    pub previousWeek: RewardGroup,
-}
+impl ManifestLanguage {
+    /// The languages to try in order when something hasn't been translated, ending with English
+    ///
+    /// e.g. pt-br falls back to es and then en, and zh-cht falls back to zh-chs and then en
+    pub fn fallback_chain(&self) -> Vec<ManifestLanguage> {
+        let mut chain = vec![*self];
+
+        match self {
+            ManifestLanguage::PortugueseBrazil | ManifestLanguage::EspanolMexico => chain.push(ManifestLanguage::Espanol),
+            ManifestLanguage::ChineseTraditional => chain.push(ManifestLanguage::ChineseSimplified),
+            ManifestLanguage::ChineseSimplified => chain.push(ManifestLanguage::ChineseTraditional),
+            _ => {}
+        }
 
-#[derive(Deserialize, Serialize, Clone)]
-pub struct RewardGroup {
-    pub categoryHash: i64,
-    pub categoryIdentifier: String,
-    pub displayProperties: DisplayProperties,
-    pub rewardEntries: RewardEntries,
-}
+        if *self != ManifestLanguage::English {
+            chain.push(ManifestLanguage::English);
+        }
 
-#[derive(Deserialize, Serialize, Clone)]
-pub struct RewardEntries {
-    #[serde(rename = "3789021730")]
-    pub nightfall: Option<RewardInfo>,
-    #[serde(rename = "248695599")]
-    pub gambit: Option<RewardInfo>,
-    #[serde(rename = "2043403989")]
-    pub raid: Option<RewardInfo>,
-    #[serde(rename = "964120289")]
-    pub pvp: Option<RewardInfo>,
-
-    // Previous week
-    #[serde(rename = "305996677")]
-    pub PWnightfall: Option<RewardInfo>,
-    #[serde(rename = "1514402550")]
-    pub PWgambit: Option<RewardInfo>,
-    #[serde(rename = "783563440")]
-    pub PWraid: Option<RewardInfo>,
-    #[serde(rename = "1478801436")]
-    pub PWpvp: Option<RewardInfo>,
-}
+        chain
+    }
 
-#[derive(Deserialize, Serialize, Clone)]
-pub struct RewardInfo {
-    pub rewardEntryHash: i64,
-    pub rewardEntryIdentifier: String,
-    pub items: Vec<RewardItem>,
-    pub vendorHash: i64,
-    pub displayProperties: DisplayProperties,
-    pub order: i32,
-    pub earnedUnlockHash: i64,
-    pub redeemedUnlockHash: i64,
-}
+    /// Find the language for a locale such as "pt-BR", "es_MX", "de" or "zh-TW"
+    ///
+    /// Locales without an exact match fall back to their base language, e.g. "fr-CA" is French
+    pub fn from_locale(locale: &str) -> Option<ManifestLanguage> {
+        let locale = locale.trim().to_lowercase().replace('_', "-");
+
+        let alias = match locale.as_str() {
+            "zh-tw" | "zh-hk" | "zh-hant" => Some(ManifestLanguage::ChineseTraditional),
+            "zh-cn" | "zh-sg" | "zh-hans" | "zh" => Some(ManifestLanguage::ChineseSimplified),
+            "es-419" => Some(ManifestLanguage::EspanolMexico),
+            "pt" | "pt-pt" => Some(ManifestLanguage::PortugueseBrazil),
+            _ => None,
+        };
 
-#[derive(Deserialize, Serialize, Clone)]
-pub struct RewardItem {
-    pub itemHash: i64,
-    pub quantity: i32,
-    pub hasConditionalVisibility: bool,
+        alias.or_else(|| ManifestLanguage::from(locale.clone()))
+            .or_else(|| locale.split('-').next().and_then(|base| ManifestLanguage::from(base.to_string())))
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
 pub struct DisplayProperties {
     pub description: String,
     pub name: String,
@@ -248,5 +319,73 @@ enumize!(ManifestEntityType, String => {
     TAGMETADATA, "TagMetadataDefinition".to_string(),
     TRAIT, "DestinyTraitDefinition".to_string(),
     TRAITCATEGORY, "DestinyTraitCategoryDefinition".to_string(),
-    UNLOCK, "DestinyUnlockDefinition".to_string()
-});
\ No newline at end of file
+    UNLOCK, "DestinyUnlockDefinition".to_string(),
+
+    LOADOUTCOLOR, "DestinyLoadoutColorDefinition".to_string(),
+    LOADOUTICON, "DestinyLoadoutIconDefinition".to_string(),
+    LOADOUTNAME, "DestinyLoadoutNameDefinition".to_string(),
+    LOADOUTCONSTANTS, "DestinyLoadoutConstantsDefinition".to_string()
+});
+
+/// An in-memory manifest for tests, a table it wasn't given is missing like in a database without it
+#[cfg(test)]
+pub(crate) struct TestSource {
+    language: ManifestLanguage,
+    tables: Vec<(ManifestEntityType, Vec<Value>)>,
+    broken: bool,
+}
+
+#[cfg(test)]
+impl TestSource {
+    pub(crate) fn new(tables: Vec<(ManifestEntityType, Vec<Value>)>) -> Self {
+        Self {
+            language: ManifestLanguage::English,
+            tables,
+            broken: false,
+        }
+    }
+
+    /// Every read fails, like a corrupted database
+    pub(crate) fn broken() -> Self {
+        Self {
+            broken: true,
+            ..TestSource::new(vec![])
+        }
+    }
+
+    pub(crate) fn with_language(mut self, language: ManifestLanguage) -> Self {
+        self.language = language;
+        self
+    }
+
+    fn table(&self, typ: ManifestEntityType) -> Result<&Vec<Value>> {
+        if self.broken {
+            return Err(anyhow!("database disk image is malformed"));
+        }
+
+        self.tables.iter().find(|(t, _)| *t == typ).map(|(_, values)| values).ok_or_else(|| anyhow!("no such table: {}", typ.get()))
+    }
+}
+
+#[cfg(test)]
+impl ManifestSource for TestSource {
+    fn get_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Value> {
+        self.find_raw(typ, hash)?.ok_or_else(|| anyhow!("No {} was found with the hash {}", typ.get(), hash))
+    }
+
+    fn find_raw(&self, typ: ManifestEntityType, hash: i64) -> Result<Option<Value>> {
+        Ok(self.table(typ)?.iter().find(|v| v["hash"] == hash).cloned())
+    }
+
+    fn get_all_raw(&self, typ: ManifestEntityType) -> Result<Vec<Value>> {
+        Ok(self.table(typ)?.clone())
+    }
+
+    fn has_type(&self, typ: ManifestEntityType) -> Result<bool> {
+        Ok(self.broken || self.tables.iter().any(|(t, _)| *t == typ))
+    }
+
+    fn get_language(&self) -> ManifestLanguage {
+        self.language
+    }
+}
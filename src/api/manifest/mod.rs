@@ -1 +1,10 @@
-pub mod manifest;
\ No newline at end of file
+pub mod manifest;
+#[cfg(feature = "sqlite")]
+pub mod local;
+pub mod json;
+pub mod definitions;
+pub mod search;
+pub mod diff;
+pub mod localized;
+pub mod export;
+pub mod graph;
\ No newline at end of file
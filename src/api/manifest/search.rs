@@ -0,0 +1,269 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use crate::api::manifest::manifest::{ManifestEntityType, ManifestLanguage, ManifestSource};
+
+/// An in-memory index over the names and descriptions of manifest definitions
+///
+/// Build it once from a LocalManifest or JsonManifest, optionally in several
+/// languages, and then search it as often as needed. Matching ignores case and
+/// accents, so "Eris Morn" finds "Éris Morn" and "SUNSHOT" finds "Sunshot".
+#[derive(Default)]
+pub struct ManifestSearchIndex {
+    entries: Vec<IndexEntry>,
+}
+
+struct IndexEntry {
+    entityType: ManifestEntityType,
+    language: ManifestLanguage,
+    hash: i64,
+    name: String,
+    description: String,
+    icon: Option<String>,
+    normalized_name: String,
+    normalized_description: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    /// The name, or any word in it, starts with the query
+    Prefix,
+    /// The query appears anywhere in the name
+    Substring,
+    /// Like Substring, but also allows a few typos
+    Fuzzy,
+}
+
+pub struct SearchOptions {
+    pub mode: SearchMode,
+    /// Only search these types, or every indexed type if empty
+    pub types: Vec<ManifestEntityType>,
+    /// Only search this language, or every indexed language if None
+    pub language: Option<ManifestLanguage>,
+    /// Also match against descriptions, which rank below any name match
+    pub include_description: bool,
+    pub limit: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            mode: SearchMode::Substring,
+            types: vec![],
+            language: None,
+            include_description: false,
+            limit: 25,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SearchResult {
+    /// The definition name, e.g. DestinyInventoryItemDefinition
+    pub entityType: String,
+    pub language: String,
+    pub hash: i64,
+    pub name: String,
+    pub description: String,
+    pub icon: Option<String>,
+    /// Higher is a better match
+    pub score: f32,
+}
+
+impl ManifestSearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index every named definition of the given types from a manifest in its language
+    pub fn add<M: ManifestSource>(&mut self, manifest: &M, types: Vec<ManifestEntityType>) -> Result<()> {
+        let language = manifest.get_language();
+
+        for typ in types {
+            for def in manifest.get_all_raw(typ)? {
+                let name = def["displayProperties"]["name"].as_str().unwrap_or_default().to_string();
+
+                if name.is_empty() || def["redacted"].as_bool().unwrap_or(false) {
+                    continue;
+                }
+
+                let description = def["displayProperties"]["description"].as_str().unwrap_or_default().to_string();
+
+                self.entries.push(IndexEntry {
+                    entityType: typ,
+                    language,
+                    hash: def["hash"].as_i64().unwrap_or(0),
+                    normalized_name: normalize(name.as_str()),
+                    normalized_description: normalize(description.as_str()),
+                    name,
+                    description,
+                    icon: def["displayProperties"]["icon"].as_str().map(|s| s.to_string()),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Find definitions matching the query, best matches first
+    pub fn search(&self, query: &str, options: &SearchOptions) -> Vec<SearchResult> {
+        let query = normalize(query);
+
+        if query.is_empty() {
+            return vec![];
+        }
+
+        let mut results = vec![];
+
+        for entry in &self.entries {
+            if !options.types.is_empty() && !options.types.contains(&entry.entityType) {
+                continue;
+            }
+
+            if let Some(language) = options.language {
+                if language != entry.language {
+                    continue;
+                }
+            }
+
+            if let Some(score) = score(entry, query.as_str(), options) {
+                results.push(SearchResult {
+                    entityType: entry.entityType.get(),
+                    language: entry.language.get(),
+                    hash: entry.hash,
+                    name: entry.name.clone(),
+                    description: entry.description.clone(),
+                    icon: entry.icon.clone(),
+                    score,
+                });
+            }
+        }
+
+        // Best score first, then shorter names, then hash so results are stable
+        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.name.len().cmp(&b.name.len()))
+            .then(a.hash.cmp(&b.hash)));
+        results.truncate(options.limit);
+
+        results
+    }
+}
+
+/// Score an entry against an already normalized query, None if it doesn't match
+fn score(entry: &IndexEntry, query: &str, options: &SearchOptions) -> Option<f32> {
+    let name = entry.normalized_name.as_str();
+
+    if name == query {
+        return Some(100.0);
+    }
+
+    if name.starts_with(query) {
+        return Some(80.0);
+    }
+
+    if name.split_whitespace().any(|word| word.starts_with(query)) {
+        return Some(60.0);
+    }
+
+    if options.mode != SearchMode::Prefix && name.contains(query) {
+        return Some(40.0);
+    }
+
+    if options.mode == SearchMode::Fuzzy {
+        // Allow roughly one typo for every four characters typed
+        let allowed = (query.chars().count() / 4).max(1);
+
+        let distance = name.split_whitespace().map(|word| levenshtein(word, query))
+            .chain(std::iter::once(levenshtein(name, query)))
+            .min()
+            .unwrap_or(usize::MAX);
+
+        if distance <= allowed {
+            return Some(30.0 - (distance as f32 / allowed as f32) * 10.0);
+        }
+    }
+
+    if options.include_description {
+        let description = entry.normalized_description.as_str();
+
+        if (options.mode == SearchMode::Prefix && description.split_whitespace().any(|word| word.starts_with(query)))
+            || (options.mode != SearchMode::Prefix && description.contains(query)) {
+            return Some(10.0);
+        }
+    }
+
+    None
+}
+
+/// Lowercase, strip accents and collapse whitespace
+pub fn normalize(s: &str) -> String {
+    let stripped = s.nfd().filter(|c| !is_combining_mark(*c)).collect::<String>().to_lowercase();
+
+    stripped.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The number of single character edits needed to turn one string into the other
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[test]
+fn search_ranking_and_normalization() {
+    let mut index = ManifestSearchIndex::new();
+
+    let entries = [
+        (ManifestEntityType::INVENTORYITEM, ManifestLanguage::English, 1, "Sunshot", "Mark enemies as solar targets"),
+        (ManifestEntityType::INVENTORYITEM, ManifestLanguage::English, 2, "Sunshot Catalyst", ""),
+        (ManifestEntityType::INVENTORYITEM, ManifestLanguage::English, 3, "The Sunshot's Glow", ""),
+        (ManifestEntityType::ACTIVITY, ManifestLanguage::English, 4, "Vault of Glass", "Sunshot not required"),
+        (ManifestEntityType::ACTIVITY, ManifestLanguage::French, 5, "Caveau de verre", "Épreuve"),
+    ];
+
+    for (typ, language, hash, name, description) in entries {
+        index.entries.push(IndexEntry {
+            entityType: typ,
+            language,
+            hash,
+            name: name.to_string(),
+            description: description.to_string(),
+            icon: None,
+            normalized_name: normalize(name),
+            normalized_description: normalize(description),
+        });
+    }
+
+    assert_eq!("epreuve", normalize("  ÉPREUVE "));
+
+    let hashes = |query: &str, options: SearchOptions| index.search(query, &options).iter().map(|r| r.hash).collect::<Vec<i64>>();
+
+    assert_eq!(vec![1, 2, 3], hashes("SUNSHOT", SearchOptions { mode: SearchMode::Prefix, ..Default::default() }));
+    assert_eq!(vec![1, 2, 3, 4], hashes("sunshot", SearchOptions { include_description: true, ..Default::default() }));
+    assert_eq!(vec![4], hashes("of gl", SearchOptions { mode: SearchMode::Substring, ..Default::default() }));
+    assert!(hashes("of gl", SearchOptions { mode: SearchMode::Prefix, ..Default::default() }).is_empty());
+    assert_eq!(vec![4], hashes("vaullt", SearchOptions { mode: SearchMode::Fuzzy, ..Default::default() }));
+    assert_eq!(vec![5], hashes("epreuve", SearchOptions { language: Some(ManifestLanguage::French), include_description: true, ..Default::default() }));
+    assert_eq!(vec![4], hashes("glass", SearchOptions { types: vec![ManifestEntityType::ACTIVITY], ..Default::default() }));
+}
@@ -6,4 +6,7 @@ pub mod Util;
 pub mod activity;
 pub mod manifest;
 pub mod items;
-pub mod oauth;
\ No newline at end of file
+pub mod oauth;
+pub mod assets;
+pub mod storage;
+pub mod stats;
\ No newline at end of file
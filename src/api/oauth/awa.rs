@@ -0,0 +1,103 @@
+//! Advanced Write Actions
+//!
+//! Some actions, such as inserting plugs that cost materials, need to be approved
+//! by the user in the companion app. An AWA request is initialized, then polled until
+//! the user approves or rejects it, which yields a single-use action token.
+
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::api::ApiClient::ApiClient;
+use crate::api::DestinyAPI::URL_BASE;
+use crate::enumize;
+
+// https://bungie-net.github.io/multi/schema_Destiny-Advanced-AwaType.html
+enumize!(AwaType, i32 => {
+    None, 0,
+    InsertPlugs, 1
+});
+
+// https://bungie-net.github.io/multi/schema_Destiny-Advanced-AwaUserSelection.html
+enumize!(AwaUserSelection, i32 => {
+    None, 0,
+    Rejected, 1,
+    Approved, 2
+});
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AwaInitializeResponse {
+    pub correlationId: String,
+    pub sentToSelf: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AwaAuthorizationResult {
+    pub userSelection: i32,
+    pub responseReason: i32,
+    pub developerNote: Option<String>,
+    pub actionToken: Option<String>,
+    pub maximumNumberOfUses: i32,
+    pub validUntil: Option<String>,
+    #[serde(rename = "type")]
+    pub awaType: i32,
+    pub membershipType: i32,
+}
+
+/// Ask the user to approve an action on an item, sending a prompt to their companion app
+pub async fn initialize(client: &ApiClient, typ: AwaType, affected_item_id: i64, membership_type: i8, character_id: i64) -> Result<AwaInitializeResponse> {
+    let body = json!({
+        "type": typ.get(),
+        "affectedItemId": affected_item_id,
+        "membershipType": membership_type,
+        "characterId": character_id,
+    });
+
+    let resp = client.post_action(format!("{}/Destiny2/Awa/Initialize/", URL_BASE), body.to_string()).await?;
+
+    Ok(serde_json::from_value::<AwaInitializeResponse>(resp)?)
+}
+
+/// Check whether the user has responded to an AWA request yet
+///
+/// Bungie responds with an error until the user has made a selection
+pub async fn get_action_token(client: &ApiClient, correlation_id: &str) -> Result<AwaAuthorizationResult> {
+    let resp = client.get(format!("{}/Destiny2/Awa/GetActionToken/{correlationId}/", URL_BASE, correlationId = correlation_id)).await?;
+    let val = serde_json::from_str::<Value>(resp.as_str())?;
+
+    ApiClient::check_error_code(&val)?;
+
+    Ok(serde_json::from_value::<AwaAuthorizationResult>(val["Response"].clone())?)
+}
+
+/// Poll an AWA request until the user approves it, returning the action token
+///
+/// Fails if the user rejects the request or doesn't respond within the timeout
+pub async fn wait_for_action_token(client: &ApiClient, correlation_id: &str, timeout: Duration, interval: Duration) -> Result<String> {
+    let start = Instant::now();
+
+    loop {
+        match get_action_token(client, correlation_id).await {
+            Ok(result) => {
+                if result.userSelection == AwaUserSelection::Rejected.get() {
+                    return Err(anyhow!("The user rejected the action request {}", correlation_id));
+                }
+
+                if let Some(token) = result.actionToken {
+                    return Ok(token);
+                }
+            }
+            Err(e) => {
+                if start.elapsed() >= timeout {
+                    return Err(anyhow!("Timed out waiting for approval of action request {} - {}", correlation_id, e));
+                }
+            }
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(anyhow!("Timed out waiting for approval of action request {}", correlation_id));
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -1 +1,2 @@
-pub mod oauth;
\ No newline at end of file
+pub mod oauth;
+pub mod awa;
\ No newline at end of file
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use crate::api::activity::activity::ActivityMode;
+use crate::api::Util::date_deserializer;
+use crate::api::Util::macros::Basic;
+use crate::enumize;
+
+// https://bungie-net.github.io/multi/schema_Destiny-HistoricalStats-Definitions-DestinyStatsGroupType.html
+enumize!(StatGroup, i32 => {
+    None, 0,
+    General, 1,
+    Weapons, 2,
+    Medals, 3,
+    ReservedGroups, 100,
+    Leaderboard, 101,
+    Activity, 102,
+    UniqueWeapon, 103,
+    Internal, 104
+});
+
+// https://bungie-net.github.io/multi/schema_Destiny-HistoricalStats-Definitions-PeriodType.html
+enumize!(PeriodType, i32 => {
+    None, 0,
+    Daily, 1,
+    AllTime, 2,
+    Activity, 3
+});
+
+/// A single stat, e.g. kills or secondsPlayed
+///
+/// Bungie uses this shape in every stats endpoint and in PGCRs. The basic value is
+/// the total, pga is the per game average and weighted is weighted by time played.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StatValue {
+    #[serde(default)]
+    pub statId: String,
+    pub basic: Basic,
+    pub pga: Option<Basic>,
+    pub weighted: Option<Basic>,
+    /// The activity the stat was recorded in, for stats like a best single game
+    ///
+    /// Bungie sends it as a string like every other int64, see get_activity_id()
+    #[serde(default)]
+    pub activityId: Option<String>,
+}
+
+impl StatValue {
+    pub fn get_activity_id(&self) -> Option<i64> {
+        self.activityId.as_ref().and_then(|id| id.parse::<i64>().ok())
+    }
+
+    pub fn get_value(&self) -> f64 {
+        self.basic.value
+    }
+
+    pub fn get_pga(&self) -> Option<f64> {
+        self.pga.as_ref().map(|p| p.value)
+    }
+}
+
+/// Stats by their stat id, as Bungie returns them
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(transparent)]
+pub struct StatMap {
+    pub values: HashMap<String, StatValue>,
+}
+
+impl StatMap {
+    pub fn get(&self, stat: &str) -> Option<&StatValue> {
+        self.values.get(stat)
+    }
+
+    /// The value of a stat, 0 if Bungie didn't include it
+    pub fn get_value(&self, stat: &str) -> f64 {
+        self.get(stat).map(|s| s.get_value()).unwrap_or(0.0)
+    }
+
+    pub fn contains(&self, stat: &str) -> bool {
+        self.values.contains_key(stat)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &StatValue)> {
+        self.values.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Stats over different periods of time, which are filled in depends on the PeriodType requested
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct StatsByPeriod {
+    #[serde(default)]
+    pub allTime: StatMap,
+    #[serde(default)]
+    pub daily: Vec<StatsPeriod>,
+    #[serde(default)]
+    pub monthly: Vec<StatsPeriod>,
+}
+
+impl StatsByPeriod {
+    /// The all time value of a stat, 0 if Bungie didn't include it
+    pub fn get_value(&self, stat: &str) -> f64 {
+        self.allTime.get_value(stat)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StatsPeriod {
+    #[serde(with = "date_deserializer")]
+    pub period: NaiveDateTime,
+    pub values: StatMap,
+}
+
+/// The career stats of an account, from /Account/{id}/Stats/
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AccountStats {
+    pub mergedDeletedCharacters: StatsByMode,
+    pub mergedAllCharacters: StatsByMode,
+    #[serde(default)]
+    pub characters: Vec<AccountCharacterStats>,
+}
+
+/// Stats by mode, such as allPvE and allPvP, plus everything merged
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct StatsByMode {
+    #[serde(default)]
+    pub results: HashMap<String, StatsByPeriod>,
+    #[serde(default)]
+    pub merged: StatsByPeriod,
+}
+
+impl StatsByMode {
+    pub fn get_mode(&self, mode: &str) -> Option<&StatsByPeriod> {
+        self.results.get(mode)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AccountCharacterStats {
+    pub characterId: String,
+    pub deleted: bool,
+    #[serde(default)]
+    pub results: HashMap<String, StatsByPeriod>,
+    #[serde(default)]
+    pub merged: StatsByPeriod,
+}
+
+/// The stats of every activity a character has played, from /Stats/AggregateActivityStats/
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AggregateActivityStats {
+    pub activityHash: i64,
+    pub values: StatMap,
+}
+
+impl AggregateActivityStats {
+    pub fn get_completions(&self) -> f64 {
+        self.values.get_value("activityCompletions")
+    }
+
+    /// The fastest completion in milliseconds, 0 if it has never been completed
+    pub fn get_fastest_completion_ms(&self) -> f64 {
+        self.values.get_value("fastestCompletionMsForActivity")
+    }
+
+    pub fn get_kills(&self) -> f64 {
+        self.values.get_value("activityKills")
+    }
+
+    pub fn get_seconds_played(&self) -> f64 {
+        self.values.get_value("activitySecondsPlayed")
+    }
+}
+
+/// The kills with an exotic weapon, from /Stats/UniqueWeapons/
+#[derive(Deserialize, Serialize, Clone)]
+pub struct UniqueWeaponStats {
+    pub referenceId: i64,
+    pub values: StatMap,
+}
+
+impl UniqueWeaponStats {
+    pub fn get_kills(&self) -> f64 {
+        self.values.get_value("uniqueWeaponKills")
+    }
+
+    pub fn get_precision_kills(&self) -> f64 {
+        self.values.get_value("uniqueWeaponPrecisionKills")
+    }
+}
+
+/// Which stats to get from /Character/{id}/Stats/
+///
+/// Bungie only accepts a range of days with PeriodType::Daily, and at most 31 days of it.
+#[derive(Clone, Default)]
+pub struct HistoricalStatsQuery {
+    groups: Vec<StatGroup>,
+    modes: Vec<ActivityMode>,
+    period_type: Option<PeriodType>,
+    day_start: Option<NaiveDate>,
+    day_end: Option<NaiveDate>,
+}
+
+impl HistoricalStatsQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_group(mut self, group: StatGroup) -> Self {
+        self.groups.push(group);
+        self
+    }
+
+    /// Get the stats of this mode, can be given more than once
+    pub fn with_mode(mut self, mode: ActivityMode) -> Self {
+        self.modes.push(mode);
+        self
+    }
+
+    pub fn with_period_type(mut self, period_type: PeriodType) -> Self {
+        self.period_type = Some(period_type);
+        self
+    }
+
+    /// Daily stats from the first day to the last day, inclusive
+    pub fn with_days(mut self, start: NaiveDate, end: NaiveDate) -> Self {
+        self.period_type = Some(PeriodType::Daily);
+        self.day_start = Some(start);
+        self.day_end = Some(end);
+        self
+    }
+
+    pub fn get_query(&self) -> Result<String> {
+        let mut params = vec![];
+
+        if !self.groups.is_empty() {
+            params.push(format!("groups={}", self.groups.iter().map(|g| g.get().to_string()).collect::<Vec<String>>().join(",")));
+        }
+
+        if !self.modes.is_empty() {
+            params.push(format!("modes={}", self.modes.iter().map(|m| m.get().to_string()).collect::<Vec<String>>().join(",")));
+        }
+
+        if let Some(period_type) = self.period_type {
+            params.push(format!("periodType={}", period_type.get()));
+        }
+
+        if let (Some(start), Some(end)) = (self.day_start, self.day_end) {
+            if end < start || (end - start).num_days() > 30 {
+                return Err(anyhow!("Daily stats can only be requested for up to 31 days, not {} to {}", start, end));
+            }
+
+            params.push(format!("daystart={}&dayend={}", start.format("%Y-%m-%d"), end.format("%Y-%m-%d")));
+        }
+
+        Ok(if params.is_empty() { String::new() } else { format!("?{}", params.join("&")) })
+    }
+}
+
+/// Join stat groups for the groups parameter of /Account/{id}/Stats/
+pub fn join_groups(groups: &[StatGroup]) -> String {
+    groups.iter().map(|g| g.get().to_string()).collect::<Vec<String>>().join(",")
+}
+
+#[test]
+fn stats_parse_and_query() {
+    let json = r#"{
+        "mergedDeletedCharacters": {"results": {}, "merged": {}},
+        "mergedAllCharacters": {
+            "results": {"allPvE": {"allTime": {"kills": {"statId": "kills", "basic": {"value": 1000.0, "displayValue": "1,000"}, "pga": {"value": 25.0, "displayValue": "25"}}}}},
+            "merged": {"allTime": {"kills": {"statId": "kills", "basic": {"value": 1500.0, "displayValue": "1,500"}},
+                "bestSingleGameKills": {"statId": "bestSingleGameKills", "basic": {"value": 96.0, "displayValue": "96"}, "activityId": "10793484711"}}}
+        },
+        "characters": [{"characterId": "1", "deleted": false, "results": {"allPvP": {}}, "merged": {}}]
+    }"#;
+
+    let stats = serde_json::from_str::<AccountStats>(json).unwrap();
+    let pve = stats.mergedAllCharacters.get_mode("allPvE").unwrap();
+
+    assert_eq!(1000.0, pve.get_value("kills"));
+    assert_eq!(Some(25.0), pve.allTime.get("kills").unwrap().get_pga());
+    assert_eq!(0.0, pve.get_value("deaths"));
+    assert_eq!(1500.0, stats.mergedAllCharacters.merged.get_value("kills"));
+    assert_eq!(Some(10793484711), stats.mergedAllCharacters.merged.allTime.get("bestSingleGameKills").unwrap().get_activity_id());
+    assert_eq!(None, stats.mergedAllCharacters.merged.allTime.get("kills").unwrap().get_activity_id());
+    assert!(stats.characters[0].results["allPvP"].allTime.is_empty());
+
+    let query = HistoricalStatsQuery::new()
+        .with_group(StatGroup::General)
+        .with_group(StatGroup::Weapons)
+        .with_mode(ActivityMode::Raid)
+        .with_days(NaiveDate::from_ymd(2022, 1, 1), NaiveDate::from_ymd(2022, 1, 31));
+
+    assert_eq!("?groups=1,2&modes=4&periodType=1&daystart=2022-01-01&dayend=2022-01-31", query.get_query().unwrap());
+    assert!(query.with_days(NaiveDate::from_ymd(2022, 1, 1), NaiveDate::from_ymd(2022, 2, 1)).get_query().is_err());
+}
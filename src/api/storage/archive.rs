@@ -0,0 +1,421 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::api::activity::activity::PGCR;
+use crate::api::activity::crawler::PgcrSink;
+use crate::api::Util::date_deserializer;
+
+/// An append-only archive of raw PGCRs, compressed in chunks with zstd
+///
+/// PGCRs are buffered and written as a chunk once there are enough of them. A chunk
+/// is a zstd frame of PGCRs as JSON lines, sorted by instance id, appended to the
+/// archive file. Every chunk has an entry in an index file next to it, {archive}.idx,
+/// recording where it is, which instance ids it holds and the time span it covers.
+///
+/// PGCRs are stored exactly as Bungie returned them, so reading one back gives the
+/// same JSON that PgcrScraper::get_pgcr_raw() returned. PGCRs that are already in the
+/// archive are skipped when appended.
+pub struct PgcrArchive {
+    path: PathBuf,
+    chunks: Vec<ChunkIndex>,
+    ids: HashMap<i64, usize>,
+    buffer: Vec<(i64, Value)>,
+    chunk_size: usize,
+    level: i32,
+}
+
+/// Where a chunk is in the archive and what it contains
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ChunkIndex {
+    pub offset: u64,
+    pub length: u64,
+    pub firstInstanceId: i64,
+    pub lastInstanceId: i64,
+    #[serde(with = "date_deserializer")]
+    pub firstPeriod: NaiveDateTime,
+    #[serde(with = "date_deserializer")]
+    pub lastPeriod: NaiveDateTime,
+    /// Sorted
+    pub instanceIds: Vec<i64>,
+}
+
+impl ChunkIndex {
+    pub fn contains(&self, instance_id: i64) -> bool {
+        self.instanceIds.binary_search(&instance_id).is_ok()
+    }
+
+    /// Whether any PGCR in the chunk is from within the time span, inclusive
+    pub fn overlaps(&self, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        self.firstPeriod <= end && self.lastPeriod >= start
+    }
+}
+
+impl PgcrArchive {
+    /// Open an archive, creating it if it doesn't exist
+    ///
+    /// Anything written after the last complete chunk, e.g. by a crash halfway
+    /// through writing one, is cut off.
+    pub fn open(path: String) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let mut chunks = vec![];
+
+        if let Ok(file) = File::open(PgcrArchive::index_path(&path)) {
+            for line in BufReader::new(file).lines() {
+                match serde_json::from_str::<ChunkIndex>(line?.as_str()) {
+                    Ok(chunk) => chunks.push(chunk),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        let end = chunks.last().map(|c| c.offset + c.length).unwrap_or(0);
+        let file = File::options().create(true).append(true).open(&path)?;
+
+        if file.metadata()?.len() < end {
+            return Err(anyhow!("The archive {} is shorter than its index says it should be", path.display()));
+        }
+
+        file.set_len(end)?;
+        PgcrArchive::write_index(&path, &chunks)?;
+
+        let mut ids = HashMap::new();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            for id in &chunk.instanceIds {
+                ids.insert(*id, i);
+            }
+        }
+
+        Ok(Self {
+            path,
+            chunks,
+            ids,
+            buffer: vec![],
+            chunk_size: 1000,
+            level: 3,
+        })
+    }
+
+    /// How many PGCRs go in a chunk, larger chunks compress better but make lookups slower
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// The zstd compression level, from 1 to 22 and 3 by default
+    ///
+    /// Higher levels compress better but are much slower to write, e.g. 19 for an archival run
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Add a PGCR as returned by PgcrScraper::get_pgcr_raw(), returns false if it was already archived
+    ///
+    /// The PGCR is only written to disk when its chunk is full or on flush().
+    pub fn append(&mut self, raw: &Value) -> Result<bool> {
+        let instance_id = PgcrArchive::get_instance_id(raw)?;
+        PgcrArchive::get_period(raw)?;
+
+        if self.contains(instance_id) {
+            return Ok(false);
+        }
+
+        self.buffer.push((instance_id, raw.clone()));
+
+        if self.buffer.len() >= self.chunk_size {
+            self.flush()?;
+        }
+
+        Ok(true)
+    }
+
+    /// Write the buffered PGCRs as a chunk
+    pub fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.buffer.sort_by_key(|(id, _)| *id);
+
+        let mut lines = String::new();
+
+        for (_, raw) in &self.buffer {
+            lines.push_str(raw.to_string().as_str());
+            lines.push('\n');
+        }
+
+        let periods = self.buffer.iter().map(|(_, raw)| PgcrArchive::get_period(raw)).collect::<Result<Vec<NaiveDateTime>>>()?;
+        let compressed = zstd::encode_all(lines.as_bytes(), self.level)?;
+
+        let mut file = File::options().append(true).open(&self.path)?;
+        let offset = file.metadata()?.len();
+        file.write_all(&compressed)?;
+        file.sync_data()?;
+
+        let chunk = ChunkIndex {
+            offset,
+            length: compressed.len() as u64,
+            firstInstanceId: self.buffer[0].0,
+            lastInstanceId: self.buffer[self.buffer.len() - 1].0,
+            firstPeriod: *periods.iter().min().unwrap_or(&periods[0]),
+            lastPeriod: *periods.iter().max().unwrap_or(&periods[0]),
+            instanceIds: self.buffer.iter().map(|(id, _)| *id).collect(),
+        };
+
+        // The chunk only counts once its index entry is written, see open()
+        let mut index = File::options().create(true).append(true).open(PgcrArchive::index_path(&self.path))?;
+        writeln!(index, "{}", serde_json::to_string(&chunk)?)?;
+        index.sync_data()?;
+
+        for id in &chunk.instanceIds {
+            self.ids.insert(*id, self.chunks.len());
+        }
+
+        self.chunks.push(chunk);
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    /// Whether the PGCR is archived or waiting to be written
+    pub fn contains(&self, instance_id: i64) -> bool {
+        self.ids.contains_key(&instance_id) || self.buffer.iter().any(|(id, _)| *id == instance_id)
+    }
+
+    /// The number of PGCRs, including those waiting to be written
+    pub fn count(&self) -> usize {
+        self.ids.len() + self.buffer.len()
+    }
+
+    pub fn get_chunks(&self) -> &Vec<ChunkIndex> {
+        &self.chunks
+    }
+
+    /// The chunks with PGCRs from within the time span
+    pub fn get_chunks_in_period(&self, start: NaiveDateTime, end: NaiveDateTime) -> Vec<&ChunkIndex> {
+        self.chunks.iter().filter(|c| c.overlaps(start, end)).collect()
+    }
+
+    /// Get a PGCR exactly as it was archived
+    pub fn get_raw(&self, instance_id: i64) -> Result<Option<Value>> {
+        if let Some((_, raw)) = self.buffer.iter().find(|(id, _)| *id == instance_id) {
+            return Ok(Some(raw.clone()));
+        }
+
+        let chunk = match self.ids.get(&instance_id) {
+            Some(i) => &self.chunks[*i],
+            None => return Ok(None),
+        };
+
+        let position = chunk.instanceIds.binary_search(&instance_id).map_err(|_| anyhow!("The archive index is inconsistent"))?;
+        let lines = PgcrArchive::decompress_chunk(&self.path, chunk)?;
+
+        // Only the line that was asked for is parsed
+        match lines.lines().nth(position) {
+            Some(line) => Ok(Some(serde_json::from_str::<Value>(line)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&self, instance_id: i64) -> Result<Option<PGCR>> {
+        Ok(match self.get_raw(instance_id)? {
+            Some(raw) => Some(PGCR::new(raw)?),
+            None => None,
+        })
+    }
+
+    /// Read every written PGCR in instance id order, decompressing one chunk at a time where possible
+    ///
+    /// PGCRs that haven't been flushed yet aren't included.
+    pub fn reader(&self) -> PgcrArchiveReader {
+        let mut pending = self.chunks.clone();
+        pending.sort_by_key(|c| c.firstInstanceId);
+
+        PgcrArchiveReader {
+            path: self.path.clone(),
+            pending: pending.into(),
+            open: BinaryHeap::new(),
+            chunks: vec![],
+        }
+    }
+
+    fn read_chunk(path: &Path, chunk: &ChunkIndex) -> Result<Vec<(i64, Value)>> {
+        let lines = PgcrArchive::decompress_chunk(path, chunk)?;
+
+        lines.lines()
+            .zip(chunk.instanceIds.iter())
+            .map(|(line, id)| Ok((*id, serde_json::from_str::<Value>(line)?)))
+            .collect()
+    }
+
+    /// The JSON lines of a chunk
+    fn decompress_chunk(path: &Path, chunk: &ChunkIndex) -> Result<String> {
+        let mut file = File::open(path)?;
+        let mut compressed = vec![0; chunk.length as usize];
+
+        file.seek(SeekFrom::Start(chunk.offset))?;
+        file.read_exact(&mut compressed)?;
+
+        Ok(String::from_utf8(zstd::decode_all(compressed.as_slice())?)?)
+    }
+
+    fn write_index(path: &Path, chunks: &[ChunkIndex]) -> Result<()> {
+        let temp = PgcrArchive::index_path(path).with_extension("idx.tmp");
+        let mut file = File::create(&temp)?;
+
+        for chunk in chunks {
+            writeln!(file, "{}", serde_json::to_string(chunk)?)?;
+        }
+
+        file.sync_data()?;
+        fs::rename(temp, PgcrArchive::index_path(path))?;
+
+        Ok(())
+    }
+
+    fn index_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(".idx");
+
+        PathBuf::from(name)
+    }
+
+    fn get_instance_id(raw: &Value) -> Result<i64> {
+        raw["activityDetails"]["instanceId"].as_str()
+            .and_then(|id| id.parse::<i64>().ok())
+            .ok_or_else(|| anyhow!("The PGCR has no instance id"))
+    }
+
+    fn get_period(raw: &Value) -> Result<NaiveDateTime> {
+        match raw["period"].as_str() {
+            Some(period) => date_deserializer::parse_from_string(period.to_string()),
+            None => Err(anyhow!("The PGCR has no period")),
+        }
+    }
+}
+
+/// Write crawled PGCRs into the archive, a chunk is written whenever the crawler checkpoints
+impl PgcrSink for PgcrArchive {
+    async fn accept(&mut self, _pgcr: &PGCR, raw: &Value) -> Result<()> {
+        self.append(raw).map(|_| ())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        PgcrArchive::flush(self)
+    }
+}
+
+/// Reads an archive in instance id order, see PgcrArchive::reader()
+///
+/// Chunks are usually written in order, in which case only one is decompressed at a
+/// time. Chunks whose instance ids overlap are merged.
+pub struct PgcrArchiveReader {
+    path: PathBuf,
+    pending: VecDeque<ChunkIndex>,
+    open: BinaryHeap<Reverse<(i64, usize)>>,
+    chunks: Vec<VecDeque<(i64, Value)>>,
+}
+
+impl PgcrArchiveReader {
+    /// Read the next PGCR exactly as it was archived
+    pub fn next_raw(&mut self) -> Result<Option<Value>> {
+        // Open every chunk that could hold something before the lowest id read so far
+        while let Some(chunk) = self.pending.front() {
+            match self.open.peek() {
+                Some(Reverse((id, _))) if *id < chunk.firstInstanceId => break,
+                _ => {}
+            }
+
+            let chunk = self.pending.pop_front().unwrap();
+            let lines = PgcrArchive::read_chunk(&self.path, &chunk)?;
+
+            if let Some((id, _)) = lines.first() {
+                self.open.push(Reverse((*id, self.chunks.len())));
+            }
+
+            self.chunks.push(lines.into());
+        }
+
+        let Reverse((_, i)) = match self.open.pop() {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+
+        let (_, raw) = self.chunks[i].pop_front().ok_or_else(|| anyhow!("The archive index is inconsistent"))?;
+
+        match self.chunks[i].front() {
+            Some((id, _)) => self.open.push(Reverse((*id, i))),
+            None => self.chunks[i] = VecDeque::new(),
+        }
+
+        Ok(Some(raw))
+    }
+}
+
+impl Iterator for PgcrArchiveReader {
+    type Item = Result<PGCR>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_raw() {
+            Ok(Some(raw)) => Some(PGCR::new(raw)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[test]
+fn archive_round_trip() {
+    let dir = std::env::temp_dir().join("rustiny_pgcr_archive");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let file = dir.join("pgcrs.archive").to_str().unwrap().to_string();
+    let pgcr = |id: i64, day: u32| serde_json::json!({
+        "period": format!("2022-01-{:02}T20:00:00Z", day),
+        "activityDetails": {"instanceId": id.to_string(), "referenceId": 3881495763_i64},
+        "extra": {"unmodeled": [1.5, null, "kept"]}
+    });
+
+    let mut archive = PgcrArchive::open(file.clone()).unwrap().with_chunk_size(3);
+
+    // The second chunk overlaps the first, so the reader has to merge them
+    for (id, day) in [(5, 5), (1, 1), (9, 9), (3, 3), (7, 7), (2, 2), (5, 5)] {
+        archive.append(&pgcr(id, day)).unwrap();
+    }
+
+    assert_eq!(6, archive.count());
+    assert_eq!(2, archive.get_chunks().len());
+    assert_eq!(Some(pgcr(2, 2)), archive.get_raw(2).unwrap());
+    archive.flush().unwrap();
+
+    // Half a chunk from a crash is dropped when the archive is opened again
+    File::options().append(true).open(&file).unwrap().write_all(&[1, 2, 3]).unwrap();
+
+    let archive = PgcrArchive::open(file).unwrap();
+    assert_eq!(6, archive.count());
+    assert_eq!(Some(pgcr(7, 7)), archive.get_raw(7).unwrap());
+    assert_eq!(None, archive.get_raw(4).unwrap());
+
+    let start = NaiveDateTime::parse_from_str("2022-01-08T00:00:00Z", "%Y-%m-%dT%H:%M:%SZ").unwrap();
+    assert_eq!(1, archive.get_chunks_in_period(start, start + chrono::Duration::days(30)).len());
+
+    let mut reader = archive.reader();
+    let mut read = vec![];
+
+    while let Some(raw) = reader.next_raw().unwrap() {
+        read.push(raw);
+    }
+
+    assert_eq!(vec![1, 2, 3, 5, 7, 9].into_iter().map(|id| pgcr(id, id as u32)).collect::<Vec<Value>>(), read);
+
+    let _ = fs::remove_dir_all(dir);
+}
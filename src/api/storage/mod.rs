@@ -0,0 +1,4 @@
+#[cfg(feature = "sqlite")]
+pub mod pgcr;
+#[cfg(feature = "archive")]
+pub mod archive;
\ No newline at end of file
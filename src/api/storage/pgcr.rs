@@ -0,0 +1,661 @@
+use std::sync::Mutex;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlite::{Connection, State, Statement};
+use crate::api::activity::activity::{ActivityHistoryResponse, ActivityMode, PGCR};
+use crate::api::activity::crawler::PgcrSink;
+use crate::api::activity::sync::{SyncStore, Watermark};
+use crate::api::Util::date_deserializer;
+#[cfg(test)]
+use crate::api::activity::activity::{test_entry, test_pgcr};
+
+const SCHEMA_VERSION: i64 = 3;
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS activities (
+    instanceId INTEGER PRIMARY KEY NOT NULL,
+    period TEXT NOT NULL,
+    referenceId INTEGER NOT NULL,
+    directorActivityHash INTEGER NOT NULL,
+    mode INTEGER NOT NULL,
+    modes TEXT NOT NULL,
+    isPrivate INTEGER NOT NULL,
+    membershipType INTEGER NOT NULL,
+    startingPhaseIndex INTEGER NOT NULL,
+    activityWasStartedFromBeginning INTEGER NOT NULL,
+    json TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS activities_period ON activities (period);
+CREATE INDEX IF NOT EXISTS activities_reference ON activities (referenceId);
+
+CREATE TABLE IF NOT EXISTS players (
+    membershipId INTEGER PRIMARY KEY NOT NULL,
+    membershipType INTEGER NOT NULL,
+    displayName TEXT NOT NULL,
+    globalDisplayName TEXT NOT NULL,
+    discriminator INTEGER NOT NULL,
+    lastSeen TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS teams (
+    instanceId INTEGER NOT NULL REFERENCES activities (instanceId) ON DELETE CASCADE,
+    teamId INTEGER NOT NULL,
+    teamName TEXT NOT NULL,
+    standing REAL NOT NULL,
+    score REAL NOT NULL,
+    PRIMARY KEY (instanceId, teamId)
+);
+
+CREATE TABLE IF NOT EXISTS entries (
+    instanceId INTEGER NOT NULL REFERENCES activities (instanceId) ON DELETE CASCADE,
+    entryIndex INTEGER NOT NULL,
+    membershipId INTEGER NOT NULL,
+    characterId INTEGER NOT NULL,
+    characterClass TEXT NOT NULL,
+    classHash INTEGER NOT NULL,
+    raceHash INTEGER NOT NULL,
+    genderHash INTEGER NOT NULL,
+    characterLevel INTEGER NOT NULL,
+    lightLevel INTEGER NOT NULL,
+    emblemHash INTEGER NOT NULL,
+    standing INTEGER NOT NULL,
+    score REAL NOT NULL,
+    assists REAL NOT NULL,
+    completed INTEGER NOT NULL,
+    deaths REAL NOT NULL,
+    kills REAL NOT NULL,
+    opponentsDefeated REAL NOT NULL,
+    efficiency REAL NOT NULL,
+    killsDeathsRatio REAL NOT NULL,
+    killsDeathsAssists REAL NOT NULL,
+    activityDurationSeconds REAL NOT NULL,
+    completionReason REAL NOT NULL,
+    fireteamId INTEGER,
+    startSeconds REAL NOT NULL,
+    timePlayedSeconds REAL NOT NULL,
+    playerCount REAL NOT NULL,
+    teamScore REAL NOT NULL,
+    averageScorePerKill REAL,
+    averageScorePerLife REAL,
+    team REAL,
+    precisionKills REAL NOT NULL,
+    weaponKillsGrenade REAL NOT NULL,
+    weaponKillsMelee REAL NOT NULL,
+    weaponKillsSuper REAL NOT NULL,
+    weaponKillsAbility REAL NOT NULL,
+    allMedalsEarned REAL,
+    PRIMARY KEY (instanceId, entryIndex)
+);
+CREATE INDEX IF NOT EXISTS entries_membership ON entries (membershipId);
+
+CREATE TABLE IF NOT EXISTS weapons (
+    instanceId INTEGER NOT NULL,
+    entryIndex INTEGER NOT NULL,
+    referenceId INTEGER NOT NULL,
+    kills REAL NOT NULL,
+    precisionKills REAL NOT NULL,
+    precisionKillsRatio REAL NOT NULL,
+    PRIMARY KEY (instanceId, entryIndex, referenceId),
+    FOREIGN KEY (instanceId, entryIndex) REFERENCES entries (instanceId, entryIndex) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS weapons_reference ON weapons (referenceId);
+
+CREATE TABLE IF NOT EXISTS watermarks (
+    key TEXT PRIMARY KEY NOT NULL,
+    period TEXT NOT NULL,
+    instanceId INTEGER NOT NULL
+);
+";
+
+/// PGCRs stored in a SQLite database, split into tables that can be queried
+///
+/// Each PGCR is stored in the activities, teams, entries and weapons tables, and the
+/// players table keeps the latest known name of everyone who was in one. Storing a
+/// PGCR again replaces it, so crawls can be repeated or resumed without duplicates.
+/// The original JSON is kept alongside, so get_pgcr() loses nothing Bungie sent.
+pub struct PgcrStore {
+    connection: Mutex<Connection>,
+}
+
+/// An activity a player was in, with how they did
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StoredActivity {
+    pub instanceId: i64,
+    #[serde(with = "date_deserializer")]
+    pub period: NaiveDateTime,
+    pub referenceId: i64,
+    pub directorActivityHash: i64,
+    pub mode: i64,
+    pub characterId: i64,
+    pub completed: bool,
+    pub kills: f64,
+    pub deaths: f64,
+    pub assists: f64,
+    pub timePlayedSeconds: f64,
+}
+
+/// A player in an activity, one per character they played it on
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StoredPlayer {
+    pub membershipId: i64,
+    pub membershipType: i64,
+    pub displayName: String,
+    pub globalDisplayName: String,
+    pub discriminator: i64,
+    pub characterId: i64,
+    pub classHash: i64,
+    pub lightLevel: i64,
+    pub completed: bool,
+    pub kills: f64,
+    pub deaths: f64,
+    pub assists: f64,
+    pub timePlayedSeconds: f64,
+}
+
+/// The kills with a weapon, added up over every stored activity of a player
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StoredWeaponStats {
+    pub referenceId: i64,
+    pub activities: i64,
+    pub kills: f64,
+    pub precisionKills: f64,
+}
+
+impl PgcrStore {
+    /// Open or create a database, ":memory:" keeps it in memory
+    pub fn open(path: String) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute("PRAGMA foreign_keys = ON")?;
+
+        let version = PgcrStore::read_one(&mut connection.prepare("PRAGMA user_version")?)?.unwrap_or(0);
+
+        if version > SCHEMA_VERSION {
+            return Err(anyhow!("The database has schema version {}, newer than the supported version {}", version, SCHEMA_VERSION));
+        }
+
+        // Before version 3 fireteamId was a REAL column, which rounds the ids
+        if version > 0 && version < 3 {
+            connection.execute("DROP TABLE IF EXISTS weapons; DROP TABLE IF EXISTS entries;")?;
+        }
+
+        connection.execute(SCHEMA)?;
+
+        if version > 0 && version < 3 {
+            PgcrStore::rewrite_all(&connection)?;
+        }
+
+        connection.execute(format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Store a PGCR, replacing it if it is already stored
+    ///
+    /// The raw JSON is stored if given, otherwise the PGCR is serialized.
+    pub fn upsert(&self, pgcr: &PGCR, raw: Option<&Value>) -> Result<()> {
+        let connection = self.lock()?;
+        let json = match raw {
+            Some(raw) => raw.to_string(),
+            None => serde_json::to_string(pgcr)?,
+        };
+
+        connection.execute("BEGIN")?;
+
+        match PgcrStore::write(&connection, pgcr, &json) {
+            Ok(_) => Ok(connection.execute("COMMIT")?),
+            Err(e) => {
+                connection.execute("ROLLBACK")?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove a PGCR, returns whether it was stored
+    pub fn remove(&self, instance_id: i64) -> Result<bool> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("DELETE FROM activities WHERE instanceId = ?")?;
+        statement.bind(1, instance_id)?;
+
+        while statement.next()? != State::Done {}
+
+        Ok(connection.change_count() > 0)
+    }
+
+    pub fn contains(&self, instance_id: i64) -> Result<bool> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("SELECT COUNT(*) FROM activities WHERE instanceId = ?")?;
+        statement.bind(1, instance_id)?;
+
+        Ok(PgcrStore::read_one(&mut statement)?.unwrap_or(0) > 0)
+    }
+
+    /// The number of stored PGCRs
+    pub fn count(&self) -> Result<i64> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("SELECT COUNT(*) FROM activities")?;
+
+        Ok(PgcrStore::read_one(&mut statement)?.unwrap_or(0))
+    }
+
+    /// The highest stored instance id, e.g. to continue crawling from
+    pub fn get_latest_instance_id(&self) -> Result<Option<i64>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("SELECT MAX(instanceId) FROM activities")?;
+
+        PgcrStore::read_one(&mut statement)
+    }
+
+    /// Get a stored PGCR as it was received
+    pub fn get_pgcr(&self, instance_id: i64) -> Result<Option<PGCR>> {
+        Ok(match self.get_pgcr_raw(instance_id)? {
+            Some(raw) => Some(PGCR::new(raw)?),
+            None => None,
+        })
+    }
+
+    pub fn get_pgcr_raw(&self, instance_id: i64) -> Result<Option<Value>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("SELECT json FROM activities WHERE instanceId = ?")?;
+        statement.bind(1, instance_id)?;
+
+        if let State::Row = statement.next()? {
+            return Ok(Some(serde_json::from_str::<Value>(statement.read::<String>(0)?.as_str())?));
+        }
+
+        Ok(None)
+    }
+
+    /// Every stored activity a player was in, newest first, optionally only of a mode
+    pub fn get_activities(&self, membership_id: i64, mode: Option<ActivityMode>) -> Result<Vec<StoredActivity>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("
+            SELECT a.instanceId, a.period, a.referenceId, a.directorActivityHash, a.mode, e.characterId, e.completed, e.kills, e.deaths, e.assists, e.timePlayedSeconds
+            FROM entries e JOIN activities a ON a.instanceId = e.instanceId
+            WHERE e.membershipId = ? AND (? IS NULL OR a.modes LIKE ?)
+            ORDER BY a.period DESC, a.instanceId DESC, e.entryIndex")?;
+
+        statement.bind(1, membership_id)?;
+        statement.bind(2, mode.map(|m| m.get() as i64))?;
+        statement.bind(3, mode.map(|m| format!("%,{},%", m.get())).as_deref())?;
+
+        let mut vec = vec![];
+
+        while let State::Row = statement.next()? {
+            vec.push(StoredActivity {
+                instanceId: statement.read::<i64>(0)?,
+                period: NaiveDateTime::parse_from_str(statement.read::<String>(1)?.as_str(), DATE_FORMAT)?,
+                referenceId: statement.read::<i64>(2)?,
+                directorActivityHash: statement.read::<i64>(3)?,
+                mode: statement.read::<i64>(4)?,
+                characterId: statement.read::<i64>(5)?,
+                completed: statement.read::<i64>(6)? != 0,
+                kills: statement.read::<f64>(7)?,
+                deaths: statement.read::<f64>(8)?,
+                assists: statement.read::<f64>(9)?,
+                timePlayedSeconds: statement.read::<f64>(10)?,
+            });
+        }
+
+        Ok(vec)
+    }
+
+    /// Every player in a stored activity, in the order of the PGCR
+    pub fn get_players(&self, instance_id: i64) -> Result<Vec<StoredPlayer>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("
+            SELECT p.membershipId, p.membershipType, p.displayName, p.globalDisplayName, p.discriminator, e.characterId, e.classHash, e.lightLevel, e.completed, e.kills, e.deaths, e.assists, e.timePlayedSeconds
+            FROM entries e JOIN players p ON p.membershipId = e.membershipId
+            WHERE e.instanceId = ?
+            ORDER BY e.entryIndex")?;
+
+        statement.bind(1, instance_id)?;
+
+        let mut vec = vec![];
+
+        while let State::Row = statement.next()? {
+            vec.push(StoredPlayer {
+                membershipId: statement.read::<i64>(0)?,
+                membershipType: statement.read::<i64>(1)?,
+                displayName: statement.read::<String>(2)?,
+                globalDisplayName: statement.read::<String>(3)?,
+                discriminator: statement.read::<i64>(4)?,
+                characterId: statement.read::<i64>(5)?,
+                classHash: statement.read::<i64>(6)?,
+                lightLevel: statement.read::<i64>(7)?,
+                completed: statement.read::<i64>(8)? != 0,
+                kills: statement.read::<f64>(9)?,
+                deaths: statement.read::<f64>(10)?,
+                assists: statement.read::<f64>(11)?,
+                timePlayedSeconds: statement.read::<f64>(12)?,
+            });
+        }
+
+        Ok(vec)
+    }
+
+    /// A player's kills per weapon over every stored activity, most kills first
+    pub fn get_weapon_stats(&self, membership_id: i64) -> Result<Vec<StoredWeaponStats>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("
+            SELECT w.referenceId, COUNT(DISTINCT w.instanceId), SUM(w.kills), SUM(w.precisionKills)
+            FROM weapons w JOIN entries e ON e.instanceId = w.instanceId AND e.entryIndex = w.entryIndex
+            WHERE e.membershipId = ?
+            GROUP BY w.referenceId
+            ORDER BY SUM(w.kills) DESC")?;
+
+        statement.bind(1, membership_id)?;
+
+        let mut vec = vec![];
+
+        while let State::Row = statement.next()? {
+            vec.push(StoredWeaponStats {
+                referenceId: statement.read::<i64>(0)?,
+                activities: statement.read::<i64>(1)?,
+                kills: statement.read::<f64>(2)?,
+                precisionKills: statement.read::<f64>(3)?,
+            });
+        }
+
+        Ok(vec)
+    }
+
+    /// Get the watermark of an incremental sync, see ActivitySync
+    pub fn get_watermark(&self, key: &str) -> Result<Option<Watermark>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("SELECT period, instanceId FROM watermarks WHERE key = ?")?;
+        statement.bind(1, key)?;
+
+        if let State::Row = statement.next()? {
+            return Ok(Some(Watermark {
+                period: NaiveDateTime::parse_from_str(statement.read::<String>(0)?.as_str(), DATE_FORMAT)?,
+                instanceId: statement.read::<i64>(1)?,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_watermark(&self, key: &str, watermark: &Watermark) -> Result<()> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("INSERT OR REPLACE INTO watermarks VALUES (?, ?, ?)")?;
+        statement.bind(1, key)?;
+        statement.bind(2, watermark.period.format(DATE_FORMAT).to_string().as_str())?;
+        statement.bind(3, watermark.instanceId)?;
+
+        PgcrStore::run(&mut statement)
+    }
+
+    fn write(connection: &Connection, pgcr: &PGCR, json: &str) -> Result<()> {
+        let details = &pgcr.activityDetails;
+        let instance_id = details.instanceId.parse::<i64>()?;
+        let period = pgcr.period.format(DATE_FORMAT).to_string();
+
+        // Removing the activity cascades to its teams, entries and weapons
+        let mut statement = connection.prepare("DELETE FROM activities WHERE instanceId = ?")?;
+        statement.bind(1, instance_id)?;
+        PgcrStore::run(&mut statement)?;
+
+        let mut statement = connection.prepare("INSERT INTO activities VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+        statement.bind(1, instance_id)?;
+        statement.bind(2, period.as_str())?;
+        statement.bind(3, details.referenceId)?;
+        statement.bind(4, details.directorActivityHash)?;
+        statement.bind(5, details.mode as i64)?;
+        statement.bind(6, format!(",{},", details.modes.iter().map(|m| m.to_string()).collect::<Vec<String>>().join(",")).as_str())?;
+        statement.bind(7, details.isPrivate as i64)?;
+        statement.bind(8, details.membershipType as i64)?;
+        statement.bind(9, pgcr.startingPhaseIndex as i64)?;
+        statement.bind(10, pgcr.activityWasStartedFromBeginning as i64)?;
+        statement.bind(11, json)?;
+        PgcrStore::run(&mut statement)?;
+
+        let mut statement = connection.prepare("INSERT INTO teams VALUES (?, ?, ?, ?, ?)")?;
+
+        for team in &pgcr.teams {
+            statement.reset()?;
+            statement.bind(1, instance_id)?;
+            statement.bind(2, team.teamId as i64)?;
+            statement.bind(3, team.teamName.as_str())?;
+            statement.bind(4, team.standing.get_value())?;
+            statement.bind(5, team.score.get_value())?;
+            PgcrStore::run(&mut statement)?;
+        }
+
+        // Names only move forward in time, so an older PGCR never overwrites a newer name
+        let mut players = connection.prepare("
+            INSERT INTO players VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (membershipId) DO UPDATE SET
+                membershipType = excluded.membershipType, displayName = excluded.displayName,
+                globalDisplayName = excluded.globalDisplayName, discriminator = excluded.discriminator, lastSeen = excluded.lastSeen
+            WHERE excluded.lastSeen >= players.lastSeen")?;
+        let mut entries = connection.prepare(format!("INSERT INTO entries VALUES ({})", vec!["?"; 37].join(", ")))?;
+        let mut weapons = connection.prepare("INSERT OR REPLACE INTO weapons VALUES (?, ?, ?, ?, ?, ?)")?;
+
+        for (index, entry) in pgcr.entries.iter().enumerate() {
+            let profile = &entry.player.destinyUserInfo;
+            let membership_id = profile.id.parse::<i64>()?;
+
+            players.reset()?;
+            players.bind(1, membership_id)?;
+            players.bind(2, profile.platform as i64)?;
+            players.bind(3, profile.platform_display_name.as_str())?;
+            players.bind(4, profile.global_display_name.as_str())?;
+            players.bind(5, profile.discriminator as i64)?;
+            players.bind(6, period.as_str())?;
+            PgcrStore::run(&mut players)?;
+
+            let player = &entry.player;
+            let values = &entry.values;
+            let extended = &entry.extended.values;
+
+            entries.reset()?;
+            entries.bind(1, instance_id)?;
+            entries.bind(2, index as i64)?;
+            entries.bind(3, membership_id)?;
+            entries.bind(4, entry.characterId.parse::<i64>()?)?;
+            entries.bind(5, player.characterClass.as_str())?;
+            entries.bind(6, player.classHash)?;
+            entries.bind(7, player.raceHash)?;
+            entries.bind(8, player.genderHash)?;
+            entries.bind(9, player.characterLevel as i64)?;
+            entries.bind(10, player.lightLevel as i64)?;
+            entries.bind(11, player.emblemHash)?;
+            entries.bind(12, entry.standing as i64)?;
+            entries.bind(13, entry.score.get_value())?;
+            entries.bind(14, values.get_assists())?;
+            entries.bind(15, values.is_completed() as i64)?;
+            entries.bind(16, values.get_deaths())?;
+            entries.bind(17, values.get_kills())?;
+            entries.bind(18, values.get_opponents_defeated())?;
+            entries.bind(19, values.get_efficiency())?;
+            entries.bind(20, values.get_kills_deaths_ratio())?;
+            entries.bind(21, values.get_kills_deaths_assists())?;
+            entries.bind(22, values.get_activity_duration_seconds())?;
+            entries.bind(23, values.get_completion_reason())?;
+            entries.bind(24, values.get_fireteam_id())?;
+            entries.bind(25, values.get_start_seconds())?;
+            entries.bind(26, values.get_time_played_seconds())?;
+            entries.bind(27, values.get_player_count())?;
+            entries.bind(28, values.get_team_score())?;
+            entries.bind(29, values.get_average_score_per_kill())?;
+            entries.bind(30, values.get_average_score_per_life())?;
+            entries.bind(31, values.get_team())?;
+            entries.bind(32, extended.get_precision_kills())?;
+            entries.bind(33, extended.get_weapon_kills_grenade())?;
+            entries.bind(34, extended.get_weapon_kills_melee())?;
+            entries.bind(35, extended.get_weapon_kills_super())?;
+            entries.bind(36, extended.get_weapon_kills_ability())?;
+            entries.bind(37, extended.get_all_medals_earned())?;
+            PgcrStore::run(&mut entries)?;
+
+            for weapon in entry.extended.weapons.iter().flatten() {
+                weapons.reset()?;
+                weapons.bind(1, instance_id)?;
+                weapons.bind(2, index as i64)?;
+                weapons.bind(3, weapon.referenceId)?;
+                weapons.bind(4, weapon.values.get_kills())?;
+                weapons.bind(5, weapon.values.get_precision_kills())?;
+                weapons.bind(6, weapon.values.get_precision_kills_ratio())?;
+                PgcrStore::run(&mut weapons)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write every stored PGCR again from its JSON, e.g. after tables were recreated
+    fn rewrite_all(connection: &Connection) -> Result<()> {
+        let mut stored = Vec::new();
+        let mut statement = connection.prepare("SELECT json FROM activities")?;
+
+        while let State::Row = statement.next()? {
+            stored.push(statement.read::<String>(0)?);
+        }
+
+        connection.execute("BEGIN")?;
+
+        for json in &stored {
+            let written = serde_json::from_str::<Value>(json)
+                .map_err(anyhow::Error::from)
+                .and_then(PGCR::new)
+                .and_then(|pgcr| PgcrStore::write(connection, &pgcr, json));
+
+            if let Err(e) = written {
+                connection.execute("ROLLBACK")?;
+                return Err(e);
+            }
+        }
+
+        Ok(connection.execute("COMMIT")?)
+    }
+
+    fn run(statement: &mut Statement) -> Result<()> {
+        while statement.next()? != State::Done {}
+
+        Ok(())
+    }
+
+    fn read_one(statement: &mut Statement) -> Result<Option<i64>> {
+        match statement.next()? {
+            State::Row => Ok(statement.read::<Option<i64>>(0)?),
+            State::Done => Ok(None),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.connection.lock().map_err(|_| anyhow!("PGCR store connection lock was poisoned"))
+    }
+}
+
+/// Store crawled PGCRs as they arrive, each in its own transaction
+impl PgcrSink for PgcrStore {
+    async fn accept(&mut self, pgcr: &PGCR, raw: &Value) -> Result<()> {
+        self.upsert(pgcr, Some(raw))
+    }
+}
+
+/// Store the PGCRs of an incremental sync, which must fetch them with ActivitySync::with_pgcrs()
+impl SyncStore for PgcrStore {
+    async fn get_watermark(&self, key: &str) -> Result<Option<Watermark>> {
+        PgcrStore::get_watermark(self, key)
+    }
+
+    async fn set_watermark(&mut self, key: &str, watermark: Watermark) -> Result<()> {
+        PgcrStore::set_watermark(self, key, &watermark)
+    }
+
+    async fn accept(&mut self, _key: &str, activity: &ActivityHistoryResponse, pgcr: Option<&PGCR>) -> Result<()> {
+        // Failing keeps the watermark where it is, rather than marking the activity as stored
+        match pgcr {
+            Some(pgcr) => self.upsert(pgcr, None),
+            None => Err(anyhow!("Activity {} has no PGCR, a sync into a PgcrStore must fetch PGCRs with with_pgcrs(true)", activity.activityDetails.instanceId)),
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_raid(instance_id: i64, period: &str, players: &[(&str, &str, f64)]) -> Value {
+    let entries = players.iter().enumerate().map(|(i, (id, name, kills))| test_entry(id, name, i as i64 + 100, 1.0, *kills, 2.0, 600.0)).collect();
+
+    test_pgcr(instance_id, 3881495763, ActivityMode::Raid, period, true, entries)
+}
+
+#[test]
+fn pgcr_store_upsert_and_query() {
+    let store = PgcrStore::open(":memory:".to_string()).unwrap();
+
+    let first = test_raid(10, "2022-01-01T20:00:00Z", &[("1", "Old Name", 10.0), ("2", "Friend", 5.0)]);
+    let second = test_raid(11, "2022-02-01T20:00:00Z", &[("1", "New Name", 20.0)]);
+
+    store.upsert(&PGCR::new(second.clone()).unwrap(), Some(&second)).unwrap();
+    store.upsert(&PGCR::new(first.clone()).unwrap(), Some(&first)).unwrap();
+
+    // Storing it again replaces it instead of duplicating the entries
+    store.upsert(&PGCR::new(first.clone()).unwrap(), None).unwrap();
+    assert_eq!(2, store.count().unwrap());
+    assert_eq!(Some(11), store.get_latest_instance_id().unwrap());
+
+    let activities = store.get_activities(1, None).unwrap();
+    assert_eq!(vec![11, 10], activities.iter().map(|a| a.instanceId).collect::<Vec<i64>>());
+    assert_eq!(2, store.get_activities(1, Some(ActivityMode::Raid)).unwrap().len());
+    assert!(store.get_activities(1, Some(ActivityMode::AllPvP)).unwrap().is_empty());
+
+    // The newest name wins even though the older PGCR was stored last
+    let players = store.get_players(10).unwrap();
+    assert_eq!(2, players.len());
+    assert_eq!("New Name", players[0].displayName);
+    assert_eq!(101, players[1].characterId);
+
+    let weapons = store.get_weapon_stats(1).unwrap();
+    assert_eq!(1, weapons.len());
+    assert_eq!(2, weapons[0].activities);
+    assert_eq!(30.0, weapons[0].kills);
+
+    assert!(store.remove(10).unwrap());
+    assert!(store.get_players(10).unwrap().is_empty());
+    assert!(store.get_pgcr(10).unwrap().is_none());
+    assert_eq!("11", store.get_pgcr(11).unwrap().unwrap().activityDetails.instanceId);
+
+    let watermark = Watermark {
+        period: NaiveDateTime::parse_from_str("2022-02-01T20:00:00Z", DATE_FORMAT).unwrap(),
+        instanceId: 11,
+    };
+
+    store.set_watermark("1/100/4", &watermark).unwrap();
+    assert_eq!(Some(watermark), store.get_watermark("1/100/4").unwrap());
+    assert_eq!(None, store.get_watermark("1/101/4").unwrap());
+}
+
+#[test]
+fn pgcr_store_migrates_fireteam_ids() {
+    let path = std::env::temp_dir().join(format!("pgcr_store_migrate_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let raw = test_raid(10, "2022-01-01T20:00:00Z", &[("1", "Name", 10.0)]);
+
+    let read_fireteam_id = |store: &PgcrStore| {
+        let connection = store.lock().unwrap();
+        let mut statement = connection.prepare("SELECT fireteamId FROM entries WHERE instanceId = 10").unwrap();
+        PgcrStore::read_one(&mut statement).unwrap()
+    };
+
+    let store = PgcrStore::open(path.to_string_lossy().to_string()).unwrap();
+    store.upsert(&PGCR::new(raw.clone()).unwrap(), Some(&raw)).unwrap();
+    assert_eq!(Some(4611686018467284386), read_fireteam_id(&store));
+
+    // An older database has its entries rebuilt from the stored JSON
+    store.lock().unwrap().execute("PRAGMA user_version = 2").unwrap();
+    drop(store);
+
+    let store = PgcrStore::open(path.to_string_lossy().to_string()).unwrap();
+    assert_eq!(Some(4611686018467284386), read_fireteam_id(&store));
+    assert_eq!(1, store.get_players(10).unwrap().len());
+    assert_eq!(10.0, store.get_weapon_stats(1).unwrap()[0].kills);
+
+    drop(store);
+    let _ = std::fs::remove_file(&path);
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::format;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -8,6 +9,7 @@ use crate::api::DestinyAPI;
 use crate::api::DestinyAPI::URL_BASE;
 use crate::api::Util::date_deserializer_optional;
 use crate::api::user::DestinyCharacter::DestinyCharacter;
+use crate::api::stats::stats::{AccountStats, HistoricalStatsQuery, join_groups, StatGroup, StatsByPeriod};
 use crate::enumize;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -147,6 +149,16 @@ impl BungieUser {
 
         Ok(vec)
     }
+
+    /// Get the raw "Response" of the profile endpoint with the given components
+    ///
+    /// Components such as ProfileInventories and CharacterInventories are private
+    /// and require the client to have an access token for this user.
+    pub async fn get_profile_components(&self, client: &ApiClient, components: Vec<DestinyComponentType>) -> Result<Value> {
+        let url = format!("{}/Destiny2/{membershipType}/Profile/{destinyMembershipId}/?components={components}", URL_BASE, membershipType = self.primary.platform, destinyMembershipId = self.primary.id, components = DestinyComponentType::join(components));
+
+        client.get_parse::<Value>(url, true).await
+    }
 }
 
 #[derive(Deserialize)]
@@ -209,6 +221,20 @@ impl DestinyProfile {
     pub async fn get_bungie_user(&self, client: &ApiClient) -> Result<BungieUser> {
         BungieUser::get_user_by_id(client, self.id.clone(), DestinyPlatform::from(self.platform).unwrap()).await
     }
+
+    /// Get the career stats of this account per mode, for every character and merged
+    pub async fn get_account_stats(&self, client: &ApiClient, groups: &[StatGroup]) -> Result<AccountStats> {
+        let url = format!("{}/Destiny2/{membershipType}/Account/{destinyMembershipId}/Stats/?groups={groups}", URL_BASE, membershipType = self.platform, destinyMembershipId = self.id, groups = join_groups(groups));
+
+        client.get_parse::<AccountStats>(url, true).await
+    }
+
+    /// Get the stats of every character merged, by mode name such as "raid" or "allPvP"
+    pub async fn get_historical_stats(&self, client: &ApiClient, query: &HistoricalStatsQuery) -> Result<HashMap<String, StatsByPeriod>> {
+        let url = format!("{}/Destiny2/{membershipType}/Account/{destinyMembershipId}/Character/0/Stats/{query}", URL_BASE, membershipType = self.platform, destinyMembershipId = self.id, query = query.get_query()?);
+
+        client.get_parse::<HashMap<String, StatsByPeriod>>(url, true).await
+    }
 }
 
 impl Default for DestinyProfile {
@@ -243,6 +269,53 @@ enumize!(DestinyPlatform, i16 => {
     All, -1
 });
 
+// https://bungie-net.github.io/multi/schema_Destiny-DestinyComponentType.html
+enumize!(DestinyComponentType, i16 => {
+    Profiles, 100,
+    VendorReceipts, 101,
+    ProfileInventories, 102,
+    ProfileCurrencies, 103,
+    ProfileProgression, 104,
+    PlatformSilver, 105,
+    Characters, 200,
+    CharacterInventories, 201,
+    CharacterProgressions, 202,
+    CharacterRenderData, 203,
+    CharacterActivities, 204,
+    CharacterEquipment, 205,
+    CharacterLoadouts, 206,
+    ItemInstances, 300,
+    ItemObjectives, 301,
+    ItemPerks, 302,
+    ItemRenderData, 303,
+    ItemStats, 304,
+    ItemSockets, 305,
+    ItemTalentGrids, 306,
+    ItemCommonData, 307,
+    ItemPlugStates, 308,
+    ItemPlugObjectives, 309,
+    ItemReusablePlugs, 310,
+    Vendors, 400,
+    VendorCategories, 401,
+    VendorSales, 402,
+    Kiosks, 500,
+    CurrencyLookups, 600,
+    PresentationNodes, 700,
+    Collectibles, 800,
+    Records, 900,
+    Transitory, 1000,
+    Metrics, 1100,
+    StringVariables, 1200,
+    Craftables, 1300
+});
+
+impl DestinyComponentType {
+    /// Join components into the comma separated list the API expects
+    pub fn join(components: Vec<DestinyComponentType>) -> String {
+        components.iter().map(|c| c.get().to_string()).collect::<Vec<String>>().join(",")
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct BnetMembership {
     #[serde(rename = "supplementalDisplayName")]
@@ -1,8 +1,15 @@
+use std::collections::HashMap;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use crate::api::Util::date_deserializer_optional;
 use anyhow::Result;
+use crate::api::ApiClient::ApiClient;
+use crate::api::DestinyAPI::URL_BASE;
+use crate::api::items::item::{DestinyItemComponent, InventoryItem};
+use crate::api::items::loadout::CharacterLoadout;
+use crate::api::user::BungieUser::DestinyComponentType;
+use crate::api::stats::stats::{AggregateActivityStats, HistoricalStatsQuery, StatsByPeriod, UniqueWeaponStats};
 use crate::BungieUser;
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -47,6 +54,96 @@ impl DestinyCharacter {
 
         Ok(char)
     }
+
+    /// Get the raw "Response" of the character endpoint with the given components
+    pub async fn get_components(&self, client: &ApiClient, components: Vec<DestinyComponentType>) -> Result<Value> {
+        let url = format!("{}/Destiny2/{membershipType}/Profile/{destinyMembershipId}/Character/{characterId}/?components={components}", URL_BASE, membershipType = self.membershipType, destinyMembershipId = self.membershipId, characterId = self.characterId, components = DestinyComponentType::join(components));
+
+        client.get_parse::<Value>(url, true).await
+    }
+
+    /// Get the items currently equipped on this character
+    pub async fn get_equipment(&self, client: &ApiClient) -> Result<Vec<InventoryItem>> {
+        let resp = self.get_components(client, vec![DestinyComponentType::CharacterEquipment]).await?;
+        let items = serde_json::from_value::<Vec<DestinyItemComponent>>(resp["equipment"]["data"]["items"].clone())?;
+
+        Ok(items.into_iter().map(|i| InventoryItem::new(i, Some(self.clone()), self.membershipType, true)).collect())
+    }
+
+    /// Get the in-game loadouts saved on this character, in slot order
+    ///
+    /// Loadouts are private, so the client needs an access token for this user
+    pub async fn get_loadouts(&self, client: &ApiClient) -> Result<Vec<CharacterLoadout>> {
+        let resp = self.get_components(client, vec![DestinyComponentType::CharacterLoadouts]).await?;
+
+        Ok(serde_json::from_value::<Vec<CharacterLoadout>>(resp["loadouts"]["data"]["loadouts"].clone())?)
+    }
+
+    /// Equip the in-game loadout in the given slot
+    pub async fn equip_loadout(&self, client: &ApiClient, loadout_index: i32) -> Result<()> {
+        self.loadout_action(client, "EquipLoadout", json!({ "loadoutIndex": loadout_index })).await
+    }
+
+    /// Save what this character is currently wearing into the given loadout slot
+    pub async fn snapshot_loadout(&self, client: &ApiClient, loadout_index: i32, color_hash: i64, icon_hash: i64, name_hash: i64) -> Result<()> {
+        self.loadout_action(client, "SnapshotLoadout", json!({
+            "loadoutIndex": loadout_index,
+            "colorHash": color_hash,
+            "iconHash": icon_hash,
+            "nameHash": name_hash,
+        })).await
+    }
+
+    /// Change the name, color and icon of a loadout without changing its items
+    pub async fn update_loadout_identifiers(&self, client: &ApiClient, loadout_index: i32, color_hash: i64, icon_hash: i64, name_hash: i64) -> Result<()> {
+        self.loadout_action(client, "UpdateLoadoutIdentifiers", json!({
+            "loadoutIndex": loadout_index,
+            "colorHash": color_hash,
+            "iconHash": icon_hash,
+            "nameHash": name_hash,
+        })).await
+    }
+
+    pub async fn clear_loadout(&self, client: &ApiClient, loadout_index: i32) -> Result<()> {
+        self.loadout_action(client, "ClearLoadout", json!({ "loadoutIndex": loadout_index })).await
+    }
+
+    /// Get the stats of this character, by mode name such as "raid" or "allPvP"
+    pub async fn get_historical_stats(&self, client: &ApiClient, query: &HistoricalStatsQuery) -> Result<HashMap<String, StatsByPeriod>> {
+        let url = format!("{}/Destiny2/{membershipType}/Account/{destinyMembershipId}/Character/{characterId}/Stats/{query}", URL_BASE, membershipType = self.membershipType, destinyMembershipId = self.membershipId, characterId = self.characterId, query = query.get_query()?);
+
+        client.get_parse::<HashMap<String, StatsByPeriod>>(url, true).await
+    }
+
+    /// Get the stats of every activity this character has played, such as completions and fastest clears
+    pub async fn get_aggregate_activity_stats(&self, client: &ApiClient) -> Result<Vec<AggregateActivityStats>> {
+        let url = format!("{}/Destiny2/{membershipType}/Account/{destinyMembershipId}/Character/{characterId}/Stats/AggregateActivityStats/", URL_BASE, membershipType = self.membershipType, destinyMembershipId = self.membershipId, characterId = self.characterId);
+        let resp = client.get_parse::<Value>(url, true).await?;
+
+        Ok(serde_json::from_value::<Vec<AggregateActivityStats>>(resp["activities"].clone())?)
+    }
+
+    /// Get the kills this character has with each exotic weapon
+    pub async fn get_unique_weapon_stats(&self, client: &ApiClient) -> Result<Vec<UniqueWeaponStats>> {
+        let url = format!("{}/Destiny2/{membershipType}/Account/{destinyMembershipId}/Character/{characterId}/Stats/UniqueWeapons/", URL_BASE, membershipType = self.membershipType, destinyMembershipId = self.membershipId, characterId = self.characterId);
+        let resp = client.get_parse::<Value>(url, true).await?;
+
+        // Characters without any exotic kills have no weapons at all
+        if resp["weapons"].is_null() {
+            return Ok(vec![]);
+        }
+
+        Ok(serde_json::from_value::<Vec<UniqueWeaponStats>>(resp["weapons"].clone())?)
+    }
+
+    async fn loadout_action(&self, client: &ApiClient, action: &str, mut body: Value) -> Result<()> {
+        body["characterId"] = json!(self.characterId.parse::<i64>()?);
+        body["membershipType"] = json!(self.membershipType);
+
+        client.post_action(format!("{}/Destiny2/Actions/Loadouts/{}/", URL_BASE, action), body.to_string()).await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
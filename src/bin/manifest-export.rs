@@ -0,0 +1,63 @@
+//! Export manifest tables for use in other tools
+//!
+//! manifest-export <api key> <manifest directory> <jsonl|csv|sqlite> <output directory> [options]
+//!
+//! Options:
+//!   --language <lc>        The manifest language, e.g. en or pt-br (default en)
+//!   --types <a,b,...>      Definition tables to export (default DestinyInventoryItemDefinition)
+//!   --columns <a,b,...>    Columns as name=path or path, e.g. name=displayProperties.name,inventory.tierType
+
+use std::env;
+use std::path::Path;
+use std::process::exit;
+use anyhow::{anyhow, Result};
+use Rustiny::api::ApiClient::ApiClient;
+use Rustiny::api::manifest::export::{entity_type_from_name, ExportColumn, ExportFormat, ManifestExporter};
+use Rustiny::api::manifest::manifest::{LocalManifest, ManifestEntityType, ManifestLanguage};
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(env::args().skip(1).collect()).await {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+async fn run(args: Vec<String>) -> Result<()> {
+    if args.len() < 4 {
+        return Err(anyhow!("Usage: manifest-export <api key> <manifest directory> <jsonl|csv|sqlite> <output directory> [--language <lc>] [--types <a,b,...>] [--columns <a,b,...>]"));
+    }
+
+    let format = ExportFormat::from_name(&args[2]).ok_or_else(|| anyhow!("Unknown format {}, expected jsonl, csv or sqlite", args[2]))?;
+
+    let mut language = ManifestLanguage::English;
+    let mut types = vec![ManifestEntityType::INVENTORYITEM];
+    let mut columns = None;
+
+    let mut options = args[4..].iter();
+
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or_else(|| anyhow!("Missing value for {}", option))?;
+
+        match option.as_str() {
+            "--language" => language = ManifestLanguage::from_locale(value).ok_or_else(|| anyhow!("Unknown language {}", value))?,
+            "--types" => types = value.split(',').map(|t| entity_type_from_name(t.trim())).collect::<Result<Vec<ManifestEntityType>>>()?,
+            "--columns" => columns = Some(value.split(',').map(ExportColumn::parse).collect::<Vec<ExportColumn>>()),
+            _ => return Err(anyhow!("Unknown option {}", option)),
+        }
+    }
+
+    let manifest = LocalManifest::load(&ApiClient::new(&args[0]), args[1].clone(), language).await?;
+
+    let mut exporter = ManifestExporter::new(&manifest);
+
+    if let Some(columns) = columns {
+        exporter = exporter.with_columns(columns);
+    }
+
+    for file in exporter.export_to_dir(types, format, Path::new(&args[3]))? {
+        println!("{}", file.display());
+    }
+
+    Ok(())
+}
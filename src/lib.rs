@@ -1,10 +1,16 @@
 use std::borrow::Borrow;
-use crate::api::activity::activity::{ActivityIdentifier, ActivityMode, PGCR, PgcrScraper};
+use crate::api::activity::activity::{ActivityMode, PGCR, PgcrScraper};
+use crate::api::activity::resolver::ActivityResolver;
 use crate::api::DestinyAPI::ApiInterface;
 use crate::api::user::BungieUser::{BungieUser, DestinyPlatform};
 use crate::api::clan::Clan::Clan;
-use crate::api::manifest::manifest::{Manifest, ManifestEntityType};
+use crate::api::manifest::json::JsonManifest;
+use crate::api::manifest::manifest::{Manifest, ManifestEntityType, ManifestLanguage};
+#[cfg(feature = "sqlite")]
+use crate::api::manifest::manifest::LocalManifest;
 use crate::api::user::DestinyCharacter::DestinyCharacter;
+#[cfg(test)]
+use crate::api::items::item::Item;
 use anyhow::Result;
 use crate::DestinyPlatform::BungieNext;
 
@@ -46,13 +52,17 @@ impl Tester {
     }
 
     async fn activity_history(&self, scraper: &PgcrScraper) {
-        println!("Acitivty History Reports - {}", scraper.get_activity_history(self.get_user(), ActivityMode::Raid).await.unwrap().len());
+        println!("Acitivty History Reports - {}", scraper.get_activity_history(&self.get_user(), ActivityMode::Raid).await.unwrap().len());
     }
 
     async fn get_unknown_activity_hashes(&self, scraper: &PgcrScraper) {
-        for ah in scraper.get_activity_history(self.get_user(), ActivityMode::ScoredNightfall).await.unwrap() {
-            if let None = ActivityIdentifier::from_identifier(format!("{}", ah.activityDetails.referenceId)) {
-                println!("{}", ah.activityDetails.referenceId);
+        let manifest = JsonManifest::load(&self.interface.client, ManifestLanguage::English, vec![ManifestEntityType::ACTIVITY, ManifestEntityType::ACTIVITYTYPE, ManifestEntityType::ACTIVITYMODE, ManifestEntityType::DESTINATION], None).await.unwrap();
+        let resolver = ActivityResolver::new(&manifest).unwrap();
+
+        for ah in scraper.get_activity_history(&self.get_user(), ActivityMode::ScoredNightfall).await.unwrap() {
+            match resolver.resolve_details(&ah.activityDetails) {
+                Some(activity) => println!("{} = {} ({})", ah.activityDetails.referenceId, activity.name, activity.canonicalName),
+                None => println!("{}", ah.activityDetails.referenceId),
             }
         }
     }
@@ -323,11 +333,11 @@ fn print_pgcr(pgcr: &PGCR) {
         println!("Character ID - {}", entry.characterId);
         println!("Standing - {}", entry.standing);
         println!("---Values---");
-        println!("Player Count - {}", entry.values.playerCount.basic.value);
-        println!("Completed - {}", entry.values.completed.basic.displayValue);
-        println!("Completion Reason - {}", entry.values.completionReason.basic.displayValue);
-        println!("Start Seconds - {}", entry.values.startSeconds.basic.value);
-        println!("Activity Duration - {}", entry.values.activityDurationSeconds.basic.displayValue);
+        println!("Player Count - {}", entry.values.get_player_count());
+        println!("Completed - {}", entry.values.is_completed());
+        println!("Completion Reason - {}", entry.values.get_completion_reason());
+        println!("Start Seconds - {}", entry.values.get_start_seconds());
+        println!("Activity Duration - {}", entry.values.get_activity_duration_seconds());
     }
 }
 
@@ -342,4 +352,31 @@ pub async fn manifest_test() {
         println!("{} = {}", s, man.manifest_get(ManifestEntityType::ACTIVITY, String::from(s)).await.unwrap());
     }
 
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn search_items() {
+    let results = Item::search(&get_api().await.client, "Ace of Spades").await.unwrap();
+
+    for r in results {
+        println!("{} - {} ({})", r.hash, r.displayProperties.name, r.weight);
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+#[ignore]
+async fn local_manifest() {
+    let man = LocalManifest::load(&get_api().await.client, String::from("manifest"), ManifestLanguage::English).await.unwrap();
+
+    println!("Version - {}", man.get_version().unwrap());
+    println!("{}", man.get_raw(ManifestEntityType::ACTIVITY, 3881495763).unwrap()["displayProperties"]["name"]);
+}
+
+#[tokio::test]
+#[ignore]
+async fn json_manifest() {
+    let man = JsonManifest::load(&get_api().await.client, ManifestLanguage::English, vec![ManifestEntityType::ACTIVITY], None).await.unwrap();
+
+    println!("{}", man.get_raw(ManifestEntityType::ACTIVITY, 3881495763).unwrap()["displayProperties"]["name"]);
+}